@@ -2,24 +2,51 @@ APPS := blink echo
 
 HERE := $(dir $(call whereami))
 
-app_src = $(call rwildcard,$(HERE)$(app)/,*.c)
-APP_SRCS := $(foreach app,$(APPS),$(app_src))
-APP_OBJS := $(APP_SRCS:.c=.o)
+APP_BUILD_DIR := $(BUILD_DIR)/apps
+
+# Apps are position independent and reach their globals through the GOT,
+# whose address the kernel passes in r9. See config/app.ld.
+APP_CFLAGS := $(CFLAGS) -fPIC -msingle-pic-base -mpic-register=r9
+APP_CFLAGS += -mno-pic-data-is-text-relative
+APP_LDFLAGS := -Tconfig/app.ld
 
 LIB_DIR := $(HERE)lib/
-LIB_SRCS := $(call rwildcard,$(LIB_DIR),*.c)
-LIB_OBJS := $(LIB_SRCS:.c=.o)
+LIB_SRCS := $(call rwildcard,$(LIB_DIR),*.c) $(call rwildcard,$(LIB_DIR),*.S)
+LIB_OBJS := $(patsubst $(HERE)%,$(APP_BUILD_DIR)/%.o,$(LIB_SRCS))
 
-APP_INC := $(addsuffix /inc/,$(APPS))
 LIB_INC := $(addsuffix inc/,$(LIB_DIR))
-INCS := $(APP_INC) $(LIB_INC)
-INC_FLAGS := $(addprefix -I,$(HERE)$(INCS))
+app_inc = $(HERE)$(1)/inc/ $(LIB_INC)
+
+app_srcs = $(call rwildcard,$(HERE)$(1)/,*.c)
+app_objs = $(patsubst $(HERE)%,$(APP_BUILD_DIR)/%.o,$(call app_srcs,$(1)))
 
-APP_OBJECTS := $(APP_OBJS) $(LIB_OBJS)
+APP_BINS := $(addprefix $(APP_BUILD_DIR)/,$(addsuffix .bin,$(APPS)))
 
-$(APP_OBJS): $(APP_SRCS) $(LIB_OBJS)
-$(LIB_OBJS): $(LIB_SRCS)
+$(APP_BUILD_DIR)/%.c.o: $(HERE)%.c | $(BUILD_DIR)
+	@echo "+ $@"
+	@mkdir -p $(dir $@)
+	@$(CC) $(APP_CFLAGS) $(addprefix -I,$(call app_inc,$(firstword $(subst /, ,$*)))) -c $< -o $@
 
-.c.o:
+$(APP_BUILD_DIR)/%.S.o: $(HERE)%.S | $(BUILD_DIR)
 	@echo "+ $@"
-	@$(CC) $(CFLAGS) $(INC_FLAGS) -c $< -o $@
+	@mkdir -p $(dir $@)
+	@$(CC) $(APP_CFLAGS) -c $< -o $@
+
+# The header's checksum covers words the linker fills in, so it is set once
+# the app is linked.
+$(APP_BUILD_DIR)/%.bin: $(APP_BUILD_DIR)/%.elf
+	@echo "$^ --> $@"
+	@$(OBJCOPY) -O binary $< $@
+	@tools/app-checksum $@
+
+.SECONDEXPANSION:
+$(APP_BUILD_DIR)/%.elf: $$(call app_objs,$$*) $(LIB_OBJS) config/app.ld
+	@echo "Linking $@"
+	@$(CC) $(APP_CFLAGS) $(APP_LDFLAGS) $(call app_objs,$*) $(LIB_OBJS) -o $@ -ffreestanding -lgcc
+
+# The apps flash region holds the apps back to back, largest first, followed
+# by a zero word so the kernel does not pick up stale apps left over from
+# earlier images.
+$(BUILD_DIR)/apps.bin: $(APP_BINS) tools/pack-apps
+	@echo "Packing $@"
+	@tools/pack-apps $@ $(APP_BINS)
@@ -1,15 +1,14 @@
 #include <commands.h>
+#include <tock.h>
 
 static uint32_t count = 0;
 
 static void
-timer_fired() {
+timer_fired(int32_t id) {
   toggle_led();
   if (!(++count % 10)) {
     println("That's 10 timer fires.");
   }
-
-  timer_subscribe(1 << 15, timer_fired);
 }
 
 static void
@@ -17,10 +16,12 @@ init() {
   toggle_led();
   println("Welcome to the C blink app!");
 
-  timer_subscribe(1 << 15, timer_fired);
+  timer_subscribe(timer_fired);
+  timer_repeating(timer_frequency() / 2);
   while(1) {
     wait();
   }
 }
 
-REGISTER_APP(blink, init);
+REGISTER_APP(blink, init,
+             PERMIT(DRIVER_TIMER) | PERMIT(DRIVER_CONSOLE) | PERMIT(DRIVER_LED));
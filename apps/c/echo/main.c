@@ -4,7 +4,7 @@
 static void
 echo_byte(uint8_t byte) {
   /* print("New char: "); */
-  /* __command(CMD_PRINTC, byte, 0); */
+  /* __command(DRIVER_CONSOLE, byte, 0); */
 }
 
 static void
@@ -14,4 +14,4 @@ init() {
   wait();
 }
 
-REGISTER_APP(echo, init);
+REGISTER_APP(echo, init, PERMIT(DRIVER_CONSOLE));
@@ -1,32 +1,152 @@
 #include <tock.h>
 #include <commands.h>
 
+/* Strings are copied here before printing, since the kernel only reads
+ * buffers in the app's RAM and string literals live in flash. */
+static char print_buf[64];
+
 void
 print(const char const *str) {
-  for (const char const *c = str; *c != '\0'; c++) {
-    __command(CMD_PRINTC, *c, 0);
+  __allow(DRIVER_CONSOLE, 0, print_buf, sizeof(print_buf));
+  const char *c = str;
+  while (*c != '\0') {
+    uint32_t len = 0;
+    while (*c != '\0' && len < sizeof(print_buf)) {
+      print_buf[len++] = *c++;
+    }
+    __command(DRIVER_CONSOLE, 1, len);
   }
 }
 
 void
 println(const char const *str) {
   print(str);
-  __command(CMD_PRINTC, '\n', 0);
+  __command(DRIVER_CONSOLE, 0, '\n');
 }
 
-void
+int32_t
 readc_subscribe(void (*f)(uint8_t)) {
-  __subscribe(SUB_READC, (uint32_t) f, 0);
+  return __subscribe(DRIVER_CONSOLE, 0, (uint32_t) f);
 }
 
-void
+int32_t
 toggle_led() {
-  __command(CMD_TOGGLE_LED, 0, 0);
+  return __command(DRIVER_LED, 0, 0);
 }
 
-void
-timer_subscribe(uint32_t time, void (*f)(void)) {
-  __subscribe(SUB_TIMER, time, (uint32_t) f);
+int32_t
+timer_subscribe(void (*f)(int32_t id)) {
+  return __subscribe(DRIVER_TIMER, 0, (uint32_t) f);
+}
+
+int32_t
+timer_oneshot(uint32_t ticks) {
+  return __command(DRIVER_TIMER, 0, ticks);
+}
+
+int32_t
+timer_repeating(uint32_t ticks) {
+  return __command(DRIVER_TIMER, 1, ticks);
+}
+
+int32_t
+timer_stop(int32_t id) {
+  return __command(DRIVER_TIMER, 2, id);
+}
+
+uint32_t
+timer_now() {
+  return __command(DRIVER_TIMER, 3, 0);
+}
+
+uint32_t
+timer_frequency() {
+  return __command(DRIVER_TIMER, 4, 0);
+}
+
+uint64_t
+timer_now64() {
+  uint32_t high, low;
+  /* The low word may wrap around between the two reads, in which case the
+   * high word changes too. */
+  do {
+    high = __command(DRIVER_TIMER, 5, 0);
+    low = __command(DRIVER_TIMER, 3, 0);
+  } while ((uint32_t) __command(DRIVER_TIMER, 5, 0) != high);
+  return (uint64_t) high << 32 | low;
+}
+
+int32_t
+ipc_register_service(void (*f)(int32_t client, void *buf, uint32_t len)) {
+  return __subscribe(DRIVER_IPC, 0, (uint32_t) f);
+}
+
+int32_t
+ipc_register_client(void (*f)(int32_t service)) {
+  return __subscribe(DRIVER_IPC, 1, (uint32_t) f);
+}
+
+/* Like print_buf, service names are copied to RAM before the kernel reads
+ * them. */
+static char ipc_name_buf[32];
+
+int32_t
+ipc_discover(const char *name) {
+  uint32_t len = 0;
+  while (name[len] != '\0') {
+    if (len == sizeof(ipc_name_buf)) {
+      return ESIZE;
+    }
+    ipc_name_buf[len] = name[len];
+    len++;
+  }
+  return __allow(DRIVER_IPC, 0, ipc_name_buf, len);
+}
+
+int32_t
+ipc_share(int32_t service, void *buf, uint32_t len) {
+  return __allow(DRIVER_IPC, service, buf, len);
+}
+
+int32_t
+ipc_notify_service(int32_t service) {
+  return __command(DRIVER_IPC, 0, service);
+}
+
+int32_t
+ipc_notify_client(int32_t client) {
+  return __command(DRIVER_IPC, 1, client);
+}
+
+int32_t
+brk(void *addr) {
+  return __memop(MEMOP_BRK, (uint32_t) addr, 0);
+}
+
+void *
+sbrk(int32_t increment) {
+  int32_t old_break = __memop(MEMOP_SBRK, (uint32_t) increment, 0);
+  return old_break < 0 ? (void *) -1 : (void *) old_break;
+}
+
+void *
+memory_start() {
+  return (void *) __memop(MEMOP_MEMORY_START, 0, 0);
+}
+
+void *
+memory_end() {
+  return (void *) __memop(MEMOP_MEMORY_END, 0, 0);
+}
+
+uint32_t
+stack_left() {
+  return __memop(MEMOP_STACK_LEFT, 0, 0);
+}
+
+int32_t
+get_stat(uint32_t stat) {
+  return __memop(MEMOP_STATS, stat, 0);
 }
 
 /* Doesn't work right now. See comment in commands.h.
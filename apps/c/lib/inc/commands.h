@@ -3,25 +3,143 @@
 
 #include <stdint.h>
 
-#define REGISTER_APP(name, init) \
-  void (*name)() __attribute__((section(".app." #name))) = init
+/* Header at the start of each app binary. Must match app_header::AppHeader
+ * in the kernel. The addresses in it are where the app is linked, see
+ * config/app.ld. */
+struct app_header {
+  uint32_t magic; /* Always APP_MAGIC */
+  uint32_t version; /* Always APP_HEADER_VERSION */
+  uint32_t total_size; /* Bytes of flash the app takes up */
+  const char *name;
+  void (*init)();
+  uint32_t data_load; /* Where the GOT and data are stored in flash */
+  uint32_t got_size;
+  uint32_t data_size; /* Bytes of GOT and data */
+  uint32_t bss_size;
+  uint32_t min_ram; /* Bytes of RAM, including stack, data and callbacks */
+  uint32_t stack_size;
+  uint32_t callback_depth; /* Callbacks that can be pending at once */
+  uint32_t priority; /* Higher runs first under the priority scheduler */
+  uint32_t restart_policy; /* One of the RESTART_ values below */
+  uint32_t max_restarts; /* Limit for RESTART_UP_TO */
+  uint32_t backoff; /* Timer ticks before the first restart, doubling after */
+  uint32_t permissions; /* Drivers the app may use, see PERMIT */
+  uint32_t checksum; /* Filled in after linking by tools/app-checksum */
+};
 
-void toggle_led();
+#define APP_MAGIC 0x4B434F54
+#define APP_HEADER_VERSION 3
+
+/* Layout of the app, defined by config/app.ld */
+extern const char _app_size[], _data_load[], _got_size[], _data_size[],
+       _bss_size[];
+
+/* What the kernel does after the app faults */
+#define RESTART_NEVER 0
+#define RESTART_ALWAYS 1
+#define RESTART_UP_TO 2
+
+/* Permits the app to use driver number driver, one of the DRIVER_ values in
+ * tock.h. Apps declare the drivers they use, combined with |, when they are
+ * registered, and the kernel refuses them any other driver with EPERM. */
+#define PERMIT(driver) (1 << (driver))
+
+/* Defaults used by REGISTER_APP and REGISTER_APP_INFO. A process gets 2048
+ * bytes of RAM, less 256 the kernel keeps for driver state. */
+#define APP_DEFAULT_RAM 1792
+#define APP_DEFAULT_STACK 1024
+#define APP_DEFAULT_CALLBACKS 10
+
+#define REGISTER_APP_HEADER(name, init, permissions, min_ram, stack_size, \
+                            callback_depth, priority, restart_policy, \
+                            max_restarts, backoff) \
+  struct app_header name __attribute__((section(".app_header"), used)) = \
+    { APP_MAGIC, APP_HEADER_VERSION, (uint32_t) _app_size, #name, init, \
+      (uint32_t) _data_load, (uint32_t) _got_size, (uint32_t) _data_size, \
+      (uint32_t) _bss_size, min_ram, stack_size, callback_depth, priority, \
+      restart_policy, max_restarts, backoff, permissions, 0 }
+
+#define REGISTER_APP_INFO(name, init, permissions, priority, restart_policy, \
+                          max_restarts, backoff) \
+  REGISTER_APP_HEADER(name, init, permissions, APP_DEFAULT_RAM, \
+                      APP_DEFAULT_STACK, APP_DEFAULT_CALLBACKS, priority, \
+                      restart_policy, max_restarts, backoff)
+
+#define REGISTER_APP_PRIORITY(name, init, permissions, priority) \
+  REGISTER_APP_INFO(name, init, permissions, priority, RESTART_NEVER, 0, 0)
+
+#define REGISTER_APP(name, init, permissions) \
+  REGISTER_APP_PRIORITY(name, init, permissions, 0)
+
+/* These return SUCCESS or a negative enum return_code from tock.h */
+int32_t toggle_led();
 void print(const char const *str);
 void println(const char const *str);
 uint8_t getchar();
 
-void timer_subscribe(uint32_t time, void (*f)(void));
-void readc_subscribe(void (*f)(uint8_t));
+int32_t readc_subscribe(void (*f)(uint8_t));
+
+/* Timers. timer_subscribe sets the function the app's timers call, with the
+ * id of the timer that went off. timer_oneshot and timer_repeating start a
+ * timer that goes off once after, or every, ticks timer ticks, and return
+ * its id or a negative return_code. ticks must be at least 4, and an app
+ * may have 4 timers running at once. timer_now wraps around at 2^32 ticks.
+ * It never fails, so its result is not a return_code even once it is too
+ * large for an int32_t. timer_now64 counts ticks since the kernel started
+ * and never wraps. */
+int32_t timer_subscribe(void (*f)(int32_t id));
+int32_t timer_oneshot(uint32_t ticks);
+int32_t timer_repeating(uint32_t ticks);
+int32_t timer_stop(int32_t id);
+uint32_t timer_now();
+uint64_t timer_now64();
+uint32_t timer_frequency();
+
+/* The heap starts after the app's static data and may grow until it would
+ * run into the stack. brk returns SUCCESS or a negative return_code; sbrk
+ * returns the old break, or (void *) -1 if the heap cannot grow that far. */
+int32_t brk(void *addr);
+void *sbrk(int32_t increment);
+
+/* Bounds of the RAM the app can use, and bytes of stack left above the
+ * heap */
+void *memory_start();
+void *memory_end();
+uint32_t stack_left();
+
+/* Counter stat the kernel keeps for the app, one of the STAT_ values in
+ * tock.h (STAT_SYSCALLS + n for system call n), or EINVAL. */
+int32_t get_stat(uint32_t stat);
+
+/* IPC between apps. An app provides a service, named after the app, by
+ * registering a callback for notifications from clients. Apps know each other
+ * by ids greater than 0. ipc_discover returns a service's id, and a client may
+ * share one buffer with each service: a power of two of at least 32 bytes,
+ * aligned to its size. A service may only notify clients that have notified
+ * it or shared a buffer with it. Ids are memory slots, so an app that takes
+ * over the slot of one that exited gets its id. The other functions return
+ * SUCCESS or a negative return_code. */
+int32_t ipc_register_service(void (*f)(int32_t client, void *buf,
+                                       uint32_t len));
+int32_t ipc_register_client(void (*f)(int32_t service));
+int32_t ipc_discover(const char *name);
+int32_t ipc_share(int32_t service, void *buf, uint32_t len);
+int32_t ipc_notify_service(int32_t service);
+int32_t ipc_notify_client(int32_t client);
 
 
 /* the C wait implementation doesn't work for some reason (gcc stacks r7 again,
  * which seems to break popping the stack, even though it really shouldn't...).
- * For now, use the assembly version in src/support/ctx_switch.S
+ * For now, use the assembly version in syscalls.S
  * void wait();
  */
 
 void __wait();
 #define wait __wait
 
+/* Ends the app. Unless its restart policy says to run it again, the kernel
+ * frees its memory. */
+void __exit() __attribute__((noreturn));
+#define exit __exit
+
 #endif
@@ -22,13 +22,88 @@
 
 SVC_ROUTINE(subscribe, 1)
 SVC_ROUTINE(command, 2)
+SVC_ROUTINE(memop, 5)
 
-// List of commands
-#define CMD_PRINTC 0
-#define CMD_TOGGLE_LED 1
+/* Lends the len bytes at ptr to a driver. They must be in the app's RAM. */
+static inline int32_t __allow(uint32_t driver, uint32_t allow_num,
+                              void *ptr, uint32_t len) {
+  register uint32_t _a asm("r0") = driver;
+  register uint32_t _b asm("r1") = allow_num;
+  register void *_c asm("r2") = ptr;
+  register uint32_t _d asm("r3") = len;
+  int32_t result;
+  asm volatile(
+      "push {r4-r11}\n\t"
+      "svc 3\n\t"
+      "pop {r4-r11}\n\t"
+      "mov %[result], r0\n\t"
+      : [result]"=r" (result)
+      : "r" (_a), "r" (_b), "r" (_c), "r" (_d)
+  );
+  return result;
+}
 
-// List of subscriptions
-#define SUB_TIMER 0
-#define SUB_READC 1
+/* Status returned by system calls. Drivers that return a value on success
+ * use nonnegative numbers for it. Must match syscall::ReturnCode in the
+ * kernel. */
+enum return_code {
+  SUCCESS = 0,
+  FAIL = -1, /* Generic failure */
+  EBUSY = -2, /* The driver cannot take the request now */
+  EINVAL = -3, /* An argument is out of range */
+  ENOMEM = -4, /* The driver ran out of room */
+  ESIZE = -5, /* A buffer is too small or too large */
+  ENODEVICE = -6, /* No driver with that number */
+  ENOSUPPORT = -7, /* No system call with that number */
+  EPERM = -8, /* The app did not declare that it uses the driver */
+};
+
+// Memop operations, passed as the first argument to memop. Must match
+// syscall.rs in the kernel.
+#define MEMOP_BRK 0
+#define MEMOP_SBRK 1
+#define MEMOP_MEMORY_START 2
+#define MEMOP_MEMORY_END 3
+#define MEMOP_HEAP_START 4
+#define MEMOP_STACK_LEFT 5
+#define MEMOP_STATS 6
+
+// Counters the kernel keeps for each app, passed to MEMOP_STATS. The first
+// six count system calls by number. Must match stats.rs in the kernel.
+#define STAT_SYSCALLS 0
+#define STAT_CALLBACKS_DELIVERED 6
+#define STAT_CALLBACKS_DROPPED 7 /* The app's callback queue was full */
+#define STAT_CONTEXT_SWITCHES 8
+#define STAT_TIMESLICES 9 /* Timeslices used up */
+
+// Driver numbers, passed as the first argument to subscribe and command.
+// Must match config.rs in the kernel. The last argument to subscribe is
+// always the callback, which must be a function in the app.
+#define DRIVER_TIMER 0   /* subscribe(0, callback): called with the id of
+                              each alarm that goes off;
+                            command(0, ticks): alarm once after ticks,
+                              at least 4;
+                            command(1, ticks): alarm every ticks, at
+                              least 4;
+                            command(2, id): cancel alarm id;
+                            command(3): current tick count, as a raw
+                              unsigned word that is never an error;
+                            command(4): ticks per second;
+                            command(5): high word of the 64-bit tick
+                              count, whose low word is command(3), also
+                              raw and never an error */
+#define DRIVER_CONSOLE 1 /* subscribe(0, callback): read;
+                            allow 0: write buffer;
+                            command(0, byte): write byte;
+                            command(1, len): write len bytes of the buffer */
+#define DRIVER_LED 2     /* command(): toggle */
+#define DRIVER_TMP006 3  /* command(): read temperature */
+#define DRIVER_IPC 4     /* subscribe(0, callback): provide a service;
+                            subscribe(1, callback): notifications from
+                            services;
+                            allow 0: look up the service named by the buffer;
+                            allow id: share the buffer with service id;
+                            command(0, id): notify service id;
+                            command(1, id): notify client id */
 
 #endif
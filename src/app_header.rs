@@ -0,0 +1,502 @@
+use core::prelude::*;
+use core::intrinsics::copy_nonoverlapping_memory;
+use core::mem;
+use core::raw;
+use core::str;
+
+/// First word of every app header ("TOCK" in memory order).
+pub const APP_MAGIC: u32 = 0x4B434F54;
+
+/// The header layout this kernel understands.
+pub const APP_HEADER_VERSION: u32 = 3;
+
+/// Longest app name the kernel reads, not counting the terminating NUL.
+pub const MAX_NAME_LEN: usize = 32;
+
+/// Address apps are linked to run from in flash. Must match the `flash`
+/// region in `config/app.ld`. Their RAM is linked to start at 0.
+pub const APP_FLASH_LINK: u32 = 0x80000000;
+
+/// Placed at the start of each app binary by the `REGISTER_APP` macros in
+/// `apps/c/lib/inc/commands.h`. Apps are position independent, so the
+/// addresses in the header are the ones the app was linked at.
+///
+/// Must match `struct app_header` in `apps/c/lib/inc/commands.h`.
+#[repr(C)]
+pub struct AppHeader {
+    /// Always `APP_MAGIC`
+    pub magic: u32,
+    /// Always `APP_HEADER_VERSION`
+    pub version: u32,
+    /// Bytes of flash the app takes up, including this header. The next app
+    /// starts right after it.
+    pub total_size: u32,
+    /// NUL terminated app name
+    pub name: u32,
+    /// The app's entry point, run as its first callback
+    pub init_fn: u32,
+    /// Where the GOT and initialized data are stored in flash. They are
+    /// copied to the bottom of the app's RAM, GOT first.
+    pub data_load: u32,
+    /// Bytes of GOT entries, which are relocated as they are copied
+    pub got_size: u32,
+    /// Bytes of GOT and initialized data
+    pub data_size: u32,
+    /// Bytes of zeroed data following the initialized data
+    pub bss_size: u32,
+    /// Bytes of RAM the app needs, including its stack, static data and
+    /// callback queue
+    pub min_ram: u32,
+    pub stack_size: u32,
+    /// Number of callbacks that can be pending at once
+    pub callback_depth: u32,
+    /// Higher numbers run first under the fixed priority scheduler.
+    pub priority: u32,
+    /// One of `RESTART_NEVER`, `RESTART_ALWAYS` or `RESTART_UP_TO`.
+    pub restart_policy: u32,
+    /// How many times a `RESTART_UP_TO` app is restarted.
+    pub max_restarts: u32,
+    /// Timer ticks to wait before the first restart. The wait doubles with
+    /// every restart after that.
+    pub backoff: u32,
+    /// The drivers the app may use: bit `n` set permits driver number `n`.
+    pub permissions: u32,
+    /// Checksum of every other word of the header and of the bytes of the
+    /// name, as `compute_checksum` folds them. Filled in after linking by
+    /// `tools/app-checksum`.
+    pub checksum: u32
+}
+
+pub const RESTART_NEVER: u32 = 0;
+pub const RESTART_ALWAYS: u32 = 1;
+pub const RESTART_UP_TO: u32 = 2;
+
+/// Why an app was refused.
+#[derive(Copy,PartialEq,Eq,Debug)]
+pub enum HeaderError {
+    BadMagic,
+    UnsupportedVersion,
+    BadChecksum,
+    /// `total_size` is too small, unaligned or runs past the end of flash.
+    BadSize,
+    BadName,
+    NoEntryPoint,
+    /// The GOT or data image lies outside the app.
+    BadLayout,
+    NoCallbacks,
+    /// The app needs more RAM than a process memory slot holds.
+    TooMuchMemory,
+    /// The MPU cannot give the process the app's flash without also giving
+    /// it part of a neighbouring app's. Checked by `load_apps`.
+    Unaligned,
+    /// The stack, static data and callback queue do not fit in the RAM the
+    /// app asked for.
+    StackTooLarge,
+}
+
+impl HeaderError {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            HeaderError::BadMagic => "not an app header",
+            HeaderError::UnsupportedVersion => "unsupported header version",
+            HeaderError::BadChecksum => "header checksum mismatch",
+            HeaderError::BadSize => "size does not fit in the app flash region",
+            HeaderError::BadName => "name is missing, too long or not UTF-8",
+            HeaderError::NoEntryPoint => "no entry point",
+            HeaderError::BadLayout => "data image lies outside the app",
+            HeaderError::NoCallbacks => "callback queue depth is zero",
+            HeaderError::TooMuchMemory => "needs more RAM than a process slot",
+            HeaderError::Unaligned => "flash is not aligned for the MPU",
+            HeaderError::StackTooLarge =>
+                "stack, data and callback queue do not fit in its RAM",
+        }
+    }
+}
+
+/// Folds `value` into a checksum. Rotating before each XOR makes the result
+/// depend on where each bit was, not just how many times it was set.
+fn fold(checksum: u32, value: u32) -> u32 {
+    (checksum << 5 | checksum >> 27) ^ value
+}
+
+impl AppHeader {
+    /// Folds every word of the header before `checksum`, then each byte of
+    /// the name if it can be read. `total_size` must already be known to be
+    /// good.
+    pub fn compute_checksum(&self) -> u32 {
+        let words: &[u32] = unsafe {
+            mem::transmute(raw::Slice {
+                data: self as *const AppHeader as *const u32,
+                len: mem::size_of::<AppHeader>() / 4 - 1
+            })
+        };
+        let checksum = words.iter().fold(0, |sum, &word| fold(sum, word));
+        match self.name() {
+            None => checksum,
+            Some(name) => name.bytes().fold(checksum, |sum, byte| {
+                fold(sum, byte as u32)
+            })
+        }
+    }
+
+    /// Whether the app declared that it uses driver number `driver_num`.
+    pub fn permits(&self, driver_num: usize) -> bool {
+        driver_num < 32 && self.permissions & (1 << driver_num) != 0
+    }
+
+    /// Entries the callback ring buffer needs to hold `callback_depth`
+    /// pending callbacks. The ring always keeps one entry free.
+    pub fn callback_slots(&self) -> usize {
+        self.callback_depth as usize + 1
+    }
+
+    /// Bytes the callback ring buffer takes up, where each entry takes
+    /// `callback_size` bytes, rounded up to a multiple of `align`.
+    pub fn callback_space(&self, callback_size: usize, align: usize) -> usize {
+        let size = self.callback_slots() * callback_size;
+        (size + align - 1) / align * align
+    }
+
+    /// Bytes of RAM taken by the app's GOT, data and bss.
+    pub fn static_size(&self) -> usize {
+        self.data_size as usize + self.bss_size as usize
+    }
+
+    /// Where the app starts in flash.
+    pub fn start(&self) -> usize {
+        self as *const AppHeader as usize
+    }
+
+    /// Translates `len` bytes at a link time flash address into where they
+    /// actually are, or `None` if they are not inside the app.
+    pub fn flash_addr(&self, link_addr: u32, len: u32) -> Option<usize> {
+        if link_addr < APP_FLASH_LINK {
+            return None;
+        }
+        let offset = link_addr - APP_FLASH_LINK;
+        if offset > self.total_size || len > self.total_size - offset {
+            None
+        } else {
+            Some(self.start() + offset as usize)
+        }
+    }
+
+    /// Where the app's entry point is in flash, including the Thumb bit.
+    pub fn entry_point(&self) -> Option<usize> {
+        self.flash_addr(self.init_fn, 2)
+    }
+
+    /// Whether `addr` is the address of a Thumb function in the app's code,
+    /// which lies between its header and its data image.
+    pub fn is_code_addr(&self, addr: usize) -> bool {
+        let code_start = self.start() + mem::size_of::<AppHeader>();
+        let code_end = match self.flash_addr(self.data_load, 0) {
+            None => { return false; },
+            Some(end) => end
+        };
+        addr & 1 == 1 && addr >= code_start && addr < code_end
+    }
+
+    /// The app's name, or `None` if it is missing, unterminated within
+    /// `MAX_NAME_LEN` bytes or not valid UTF-8.
+    pub fn name(&self) -> Option<&'static str> {
+        let name = match self.flash_addr(self.name, 1) {
+            None => { return None; },
+            Some(addr) => addr as *const u8
+        };
+        let end = self.start() + self.total_size as usize;
+        unsafe {
+            let mut len = 0;
+            while *name.offset(len as isize) != 0 {
+                len += 1;
+                if len > MAX_NAME_LEN || name as usize + len >= end {
+                    return None;
+                }
+            }
+            let bytes: &'static [u8] = mem::transmute(raw::Slice {
+                data: name,
+                len: len
+            });
+            str::from_utf8(bytes).ok()
+        }
+    }
+
+    /// Where the app after this one would start, or `None` if `total_size`
+    /// cannot be right for an app in a flash region ending at `region_end`.
+    pub fn next(&self, region_end: usize) -> Option<usize> {
+        let size = self.total_size as usize;
+        if size < mem::size_of::<AppHeader>() || size % 4 != 0 ||
+                size > region_end - self.start() {
+            None
+        } else {
+            Some(self.start() + size)
+        }
+    }
+
+    /// Checks that the header is well formed, that the app lies within a
+    /// flash region ending at `region_end` and that it fits in a process
+    /// memory slot of `slot_size` bytes, where each pending callback takes
+    /// `callback_size` bytes and the callback queue is rounded up to a
+    /// multiple of `callback_align` bytes.
+    pub fn validate(&self, region_end: usize, slot_size: usize,
+                    callback_size: usize, callback_align: usize)
+            -> Result<(), HeaderError> {
+        if self.magic != APP_MAGIC {
+            return Err(HeaderError::BadMagic);
+        }
+        if self.version != APP_HEADER_VERSION {
+            return Err(HeaderError::UnsupportedVersion);
+        }
+        if self.next(region_end).is_none() {
+            return Err(HeaderError::BadSize);
+        }
+        if self.checksum != self.compute_checksum() {
+            return Err(HeaderError::BadChecksum);
+        }
+        if self.name().is_none() {
+            return Err(HeaderError::BadName);
+        }
+        if self.entry_point().is_none() {
+            return Err(HeaderError::NoEntryPoint);
+        }
+        if self.flash_addr(self.data_load, self.data_size).is_none() ||
+                self.got_size > self.data_size || self.got_size % 4 != 0 {
+            return Err(HeaderError::BadLayout);
+        }
+        if self.callback_depth == 0 {
+            return Err(HeaderError::NoCallbacks);
+        }
+        if self.min_ram as usize > slot_size {
+            return Err(HeaderError::TooMuchMemory);
+        }
+        // The process needs some memory of its own besides the queue.
+        let callbacks = self.callback_space(callback_size, callback_align);
+        if callbacks + self.static_size() + self.stack_size as usize >
+                self.min_ram as usize || callbacks >= slot_size {
+            return Err(HeaderError::StackTooLarge);
+        }
+        Ok(())
+    }
+
+    /// Copies the app's GOT and data into the bottom of `ram`, zeroes its
+    /// bss and points the GOT entries at where the app actually is. The app
+    /// must have been validated and `ram` must hold at least
+    /// `static_size()` bytes.
+    pub unsafe fn load(&self, ram: &mut [u8]) {
+        let ram_start = ram.as_ptr() as usize;
+        let data = self.flash_addr(self.data_load, self.data_size)
+            .expect("load of invalid app");
+        copy_nonoverlapping_memory(ram.as_mut_ptr(), data as *const u8,
+                                   self.data_size as usize);
+        for byte in ram[self.data_size as usize..self.static_size()]
+                .iter_mut() {
+            *byte = 0;
+        }
+
+        let got = ram_start as *mut u32;
+        for i in range(0, self.got_size as isize / 4) {
+            let entry = *got.offset(i);
+            *got.offset(i) = if entry >= APP_FLASH_LINK {
+                (self.start() + (entry - APP_FLASH_LINK) as usize) as u32
+            } else {
+                (ram_start + entry as usize) as u32
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use core::mem;
+    use super::*;
+
+    const SLOT_SIZE: usize = 2048;
+    const CALLBACK_SIZE: usize = 20;
+    const CALLBACK_ALIGN: usize = 256;
+
+    /// An app image as the linker would lay it out: header, name, then the
+    /// data image with a two entry GOT in front.
+    #[repr(C)]
+    struct Image {
+        header: AppHeader,
+        name: [u8; 8],
+        data: [u32; 3]
+    }
+
+    fn offset_of<T>(image: &Image, field: &T) -> u32 {
+        (field as *const T as usize - image as *const Image as usize) as u32
+    }
+
+    fn image() -> Image {
+        let mut image = Image {
+            header: AppHeader {
+                magic: APP_MAGIC,
+                version: APP_HEADER_VERSION,
+                total_size: mem::size_of::<Image>() as u32,
+                name: 0,
+                init_fn: APP_FLASH_LINK + 0x41,
+                data_load: 0,
+                got_size: 8,
+                data_size: 12,
+                bss_size: 8,
+                min_ram: 1024,
+                stack_size: 512,
+                callback_depth: 10,
+                priority: 0,
+                restart_policy: RESTART_NEVER,
+                max_restarts: 0,
+                backoff: 0,
+                permissions: 0b101,
+                checksum: 0
+            },
+            name: *b"blink\0\0\0",
+            // GOT entries for a flash symbol and a RAM symbol, then data.
+            data: [APP_FLASH_LINK + 0x10, 0x8, 0xdeadbeef]
+        };
+        image.header.name = APP_FLASH_LINK + offset_of(&image, &image.name);
+        image.header.data_load =
+            APP_FLASH_LINK + offset_of(&image, &image.data);
+        image.header.checksum = image.header.compute_checksum();
+        image
+    }
+
+    fn validate(image: &Image) -> Result<(), HeaderError> {
+        let end = image as *const Image as usize + mem::size_of::<Image>();
+        image.header.validate(end, SLOT_SIZE, CALLBACK_SIZE, CALLBACK_ALIGN)
+    }
+
+    #[test]
+    fn accepts_valid_image() {
+        let image = image();
+        assert_eq!(validate(&image), Ok(()));
+        assert_eq!(image.header.name(), Some("blink"));
+        assert_eq!(image.header.entry_point(),
+                   Some(&image as *const Image as usize + 0x41));
+    }
+
+    #[test]
+    fn permits_declared_drivers_only() {
+        let image = image();
+        assert!(image.header.permits(0));
+        assert!(!image.header.permits(1));
+        assert!(image.header.permits(2));
+        assert!(!image.header.permits(32));
+    }
+
+    #[test]
+    fn checksum_covers_permissions() {
+        let mut image = image();
+        image.header.permissions |= 1 << 4;
+        assert_eq!(validate(&image), Err(HeaderError::BadChecksum));
+    }
+
+    #[test]
+    fn checksum_covers_linker_fields_and_name() {
+        let mut bad_entry = image();
+        bad_entry.header.init_fn += 2;
+        assert_eq!(validate(&bad_entry), Err(HeaderError::BadChecksum));
+
+        let mut bad_name = image();
+        bad_name.name[0] = b'B';
+        assert_eq!(validate(&bad_name), Err(HeaderError::BadChecksum));
+
+        // Swapping two words changes the checksum, unlike with a plain XOR.
+        let mut swapped = image();
+        swapped.header.got_size = 12;
+        swapped.header.data_size = 8;
+        assert_eq!(validate(&swapped), Err(HeaderError::BadChecksum));
+    }
+
+    #[test]
+    fn code_lies_between_header_and_data() {
+        let image = image();
+        let start = &image as *const Image as usize;
+        let code = start + mem::size_of::<AppHeader>();
+        assert!(image.header.is_code_addr(code | 1));
+        assert!(!image.header.is_code_addr(code));
+        assert!(!image.header.is_code_addr(start + 1));
+        assert!(!image.header.is_code_addr(image.data.as_ptr() as usize + 1));
+        assert!(!image.header.is_code_addr(0x41));
+    }
+
+    #[test]
+    fn rejects_bad_magic_and_checksum() {
+        let mut image = image();
+        image.header.priority = 3;
+        assert_eq!(validate(&image), Err(HeaderError::BadChecksum));
+        image.header.magic = 0xffffffff;
+        assert_eq!(validate(&image), Err(HeaderError::BadMagic));
+    }
+
+    #[test]
+    fn rejects_size_past_region() {
+        let mut image = image();
+        image.header.total_size += 4;
+        assert_eq!(validate(&image), Err(HeaderError::BadSize));
+        image.header.total_size = 2;
+        assert_eq!(validate(&image), Err(HeaderError::BadSize));
+    }
+
+    #[test]
+    fn rejects_pointers_outside_image() {
+        let mut bad_entry = image();
+        bad_entry.header.init_fn = 0x41;
+        bad_entry.header.checksum = bad_entry.header.compute_checksum();
+        assert_eq!(validate(&bad_entry), Err(HeaderError::NoEntryPoint));
+
+        let mut bad_data = image();
+        bad_data.header.data_size = 16;
+        bad_data.header.checksum = bad_data.header.compute_checksum();
+        assert_eq!(validate(&bad_data), Err(HeaderError::BadLayout));
+    }
+
+    #[test]
+    fn rejects_app_that_does_not_fit() {
+        let mut image = image();
+        image.header.stack_size = 1024;
+        image.header.checksum = image.header.compute_checksum();
+        assert_eq!(validate(&image), Err(HeaderError::StackTooLarge));
+
+        image.header.min_ram = SLOT_SIZE as u32 + 4;
+        image.header.checksum = image.header.compute_checksum();
+        assert_eq!(validate(&image), Err(HeaderError::TooMuchMemory));
+    }
+
+    #[test]
+    fn callback_queue_takes_whole_blocks() {
+        let mut image = image();
+        // 11 entries of 20 bytes fit in one 256 byte block.
+        assert_eq!(image.header.callback_space(CALLBACK_SIZE, CALLBACK_ALIGN),
+                   CALLBACK_ALIGN);
+        image.header.callback_depth = 12;
+        assert_eq!(image.header.callback_space(CALLBACK_SIZE, CALLBACK_ALIGN),
+                   2 * CALLBACK_ALIGN);
+
+        // Fits only if the queue is not rounded up.
+        image.header.callback_depth = 10;
+        image.header.stack_size = image.header.min_ram -
+            image.header.static_size() as u32 - 11 * CALLBACK_SIZE as u32;
+        image.header.checksum = image.header.compute_checksum();
+        assert_eq!(validate(&image), Err(HeaderError::StackTooLarge));
+        assert_eq!(image.header.validate(
+            image.header.start() + mem::size_of::<Image>(), SLOT_SIZE,
+            CALLBACK_SIZE, 1), Ok(()));
+    }
+
+    #[test]
+    fn load_relocates_got_and_zeroes_bss() {
+        let image = image();
+        let mut ram = [0xffu8; 20];
+        unsafe { image.header.load(&mut ram); }
+
+        let words: &[u32; 5] = unsafe { mem::transmute(&ram) };
+        let image_start = &image as *const Image as u32;
+        let ram_start = &ram as *const [u8; 20] as u32;
+        assert_eq!(words[0], image_start + 0x10);
+        assert_eq!(words[1], ram_start + 0x8);
+        assert_eq!(words[2], 0xdeadbeef);
+        assert_eq!(words[3], 0);
+        assert_eq!(words[4], 0);
+    }
+}
@@ -27,6 +27,27 @@ impl <T> ArrayList<T> {
         return true;
     }
 
+    /// Puts `elm` in place of the first element `replaceable` holds for, or
+    /// adds it if there is none. Returns `None` if there is no room for it.
+    pub fn add_or_replace<F>(&mut self, elm: T, replaceable: F)
+            -> Option<&mut T> where F: Fn(&T) -> bool {
+        let mut idx = self.len;
+        for i in range(0, self.len) {
+            if replaceable(&self[i]) {
+                idx = i;
+                break;
+            }
+        }
+        if idx == self.len {
+            if !self.add(elm) {
+                return None;
+            }
+        } else {
+            self[idx] = elm;
+        }
+        Some(&mut self[idx])
+    }
+
     pub fn iterator(&mut self) -> ArrayListIterator<T> {
         ArrayListIterator{list: self, cur_index: 0}
     }
@@ -103,3 +124,70 @@ impl<'a, T: 'a> Iterator for CircularArrayListIterator<'a, T> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use super::ArrayList;
+
+    #[test]
+    fn adds_up_to_capacity() {
+        let mut buf = [0u32; 3];
+        let mut list = unsafe { ArrayList::new(3, buf.as_mut_ptr()) };
+        for i in range(0, 3) {
+            assert!(list.add(i * 10));
+        }
+        assert!(!list.add(30));
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[0], 0);
+        assert_eq!(list[2], 20);
+    }
+
+    #[test]
+    fn replaces_elements_when_it_can() {
+        let mut buf = [0u32; 2];
+        let mut list = unsafe { ArrayList::new(2, buf.as_mut_ptr()) };
+        // 0 stands for an entry that has been given up.
+        fn unused(elm: &u32) -> bool { *elm == 0 }
+        assert_eq!(list.add_or_replace(1, unused).map(|elm| *elm), Some(1));
+        assert_eq!(list.add_or_replace(2, unused).map(|elm| *elm), Some(2));
+        assert_eq!(list.add_or_replace(3, unused), None);
+
+        list[0] = 0;
+        assert_eq!(list.add_or_replace(3, unused).map(|elm| *elm), Some(3));
+        assert_eq!((list.len(), list[0], list[1]), (2, 3, 2));
+    }
+
+    #[test]
+    fn iterates_over_elements() {
+        let mut buf = [0u32; 4];
+        let mut list = unsafe { ArrayList::new(4, buf.as_mut_ptr()) };
+        list.add(1);
+        list.add(2);
+        for elm in list.iterator() {
+            *elm += 1;
+        }
+        let sum = list.iterator().fold(0, |sum, elm| sum + *elm);
+        assert_eq!(sum, 5);
+    }
+
+    #[test]
+    fn circular_iterator_wraps() {
+        let mut buf = [0u32; 4];
+        let mut list = unsafe { ArrayList::new(4, buf.as_mut_ptr()) };
+        list.add(7);
+        list.add(8);
+        let mut iter = list.circular_iterator();
+        assert_eq!(iter.next().map(|elm| *elm), Some(7));
+        assert_eq!(iter.next().map(|elm| *elm), Some(8));
+        assert_eq!(iter.next().map(|elm| *elm), Some(7));
+    }
+
+    #[test]
+    #[should_fail]
+    fn panics_past_end() {
+        let mut buf = [0u32; 2];
+        let mut list = unsafe { ArrayList::new(2, buf.as_mut_ptr()) };
+        list.add(1);
+        list[1];
+    }
+}
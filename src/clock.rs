@@ -0,0 +1,137 @@
+use core::prelude::*;
+use hil::timer::OverflowTimer;
+
+/// Extends a timer's 32-bit counter to 64 bits by counting the times it
+/// wraps around, so timestamps never wrap.
+pub struct Clock {
+    /// Times the counter has wrapped around
+    pub overflows: u32,
+    /// Ticks per second
+    pub frequency: u32
+}
+
+impl Clock {
+    pub fn new(frequency: u32) -> Clock {
+        Clock { overflows: 0, frequency: frequency }
+    }
+
+    /// Counts one wraparound of the counter. Called by the timer's overflow
+    /// interrupt handler once it has cleared the overflow.
+    pub fn overflow(&mut self) {
+        self.overflows += 1;
+    }
+
+    /// Ticks since the counter started. Must be called with interrupts
+    /// masked, so the overflow cannot be counted between reading the two
+    /// halves.
+    pub fn ticks<T: OverflowTimer>(&self, timer: &T) -> u64 {
+        let mut low = timer.now();
+        let mut high = self.overflows;
+        if timer.overflow_pending() {
+            // The counter wrapped around, perhaps after `low` was read, and
+            // the interrupt has not counted it yet. Reading it again gives a
+            // value from after the wraparound.
+            low = timer.now();
+            high += 1;
+        }
+        (high as u64) << 32 | low as u64
+    }
+
+    pub fn micros(&self, ticks: u64) -> u64 {
+        self.convert(ticks, 1000000)
+    }
+
+    pub fn millis(&self, ticks: u64) -> u64 {
+        self.convert(ticks, 1000)
+    }
+
+    /// `ticks` in units `per_second` of which make a second. Whole seconds
+    /// are converted separately so nothing overflows on the way.
+    fn convert(&self, ticks: u64, per_second: u64) -> u64 {
+        if self.frequency == 0 {
+            return 0;
+        }
+        let frequency = self.frequency as u64;
+        (ticks / frequency).saturating_mul(per_second) +
+            ticks % frequency * per_second / frequency
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use core::cell::Cell;
+    use hil::timer::{OverflowTimer, Timer};
+    use super::Clock;
+
+    /// A counter that moves on by `step` each time it is read, wrapping
+    /// around as the hardware does.
+    struct MockCounter {
+        now: Cell<u32>,
+        step: u32,
+        overflow: Cell<bool>
+    }
+
+    impl Timer for MockCounter {
+        fn now(&self) -> u32 {
+            let now = self.now.get();
+            let next = now.wrapping_add(self.step);
+            if next < now {
+                self.overflow.set(true);
+            }
+            self.now.set(next);
+            now
+        }
+
+        fn frequency(&self) -> u32 { 1000 }
+        fn set_alarm(&mut self, _: u32) {}
+        fn disable_alarm(&mut self) {}
+    }
+
+    impl OverflowTimer for MockCounter {
+        fn overflow_pending(&self) -> bool {
+            self.overflow.get()
+        }
+    }
+
+    fn counter(now: u32, step: u32) -> MockCounter {
+        MockCounter { now: Cell::new(now), step: step,
+                      overflow: Cell::new(false) }
+    }
+
+    #[test]
+    fn counts_overflows() {
+        let mut clock = Clock::new(1000);
+        let timer = counter(5, 0);
+        assert_eq!(clock.ticks(&timer), 5);
+        clock.overflow();
+        clock.overflow();
+        assert_eq!(clock.ticks(&timer), 2 << 32 | 5);
+    }
+
+    #[test]
+    fn counts_overflow_the_interrupt_has_not_handled() {
+        let mut clock = Clock::new(1000);
+        clock.overflow();
+
+        // Wraps around right after the first read.
+        let timer = counter(0xffff_fffe, 4);
+        assert_eq!(clock.ticks(&timer), 2 << 32 | 2);
+
+        // Wrapped around before the first read.
+        let timer = counter(3, 1);
+        timer.overflow.set(true);
+        assert_eq!(clock.ticks(&timer), 2 << 32 | 4);
+    }
+
+    #[test]
+    fn converts_ticks() {
+        let clock = Clock::new(57600);
+        assert_eq!(clock.micros(57600), 1000000);
+        assert_eq!(clock.micros(1), 17);
+        assert_eq!(clock.millis(57600 * 3 + 28800), 3500);
+        // Enough ticks to overflow if multiplied by 10^6 before dividing.
+        assert_eq!(clock.micros(57600 << 40), 1000000 << 40);
+        assert_eq!(Clock::new(0).millis(1234), 0);
+    }
+}
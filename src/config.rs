@@ -1,73 +1,374 @@
 use core::mem;
 use core::prelude::*;
-use core::intrinsics;
+use core::raw;
 use platform::sam4l::{usart, ast, gpio};
 use platform::sam4l;
-use hil::timer::AlarmHandler;
+use hil::{self, GPIOPin, UART};
+use hil::timer::Timer;
 use hil::rng::RNG;
+use support;
 use util;
+use clock::Clock;
+use deferred_call;
+use driver::Driver;
 use drivers;
-use process;
+use drivers::timer::{AlarmId, AlarmQueue, MIN_ALARM_TICKS};
+use grant::Grant;
+use ipc::Ipc;
+use process::{self, Process};
+use ring_buffer::RingBuffer;
+use shell::{self, Command, Input, LineBuffer, ParseError};
 use syscall;
+use syscall::ReturnCode;
+
+/// Driver numbers processes use to reach each driver. Must match `tock.h`.
+pub const TIMER_DRIVER: usize = 0;
+pub const CONSOLE_DRIVER: usize = 1;
+pub const LED_DRIVER: usize = 2;
+pub const TMP006_DRIVER: usize = 3;
+pub const IPC_DRIVER: usize = 4;
+
+/// Processor clock ticks a process may run before it is preempted: 10ms at
+/// the 48MHz core clock set up by the bootloader.
+pub const TIMESLICE_TICKS: usize = 48000000 / 100;
+
+/// Deferred calls interrupt handlers leave for the main loop, run by
+/// `deferred_call`.
+pub const TIMER_CALL: usize = 0;
+pub const CONSOLE_CALL: usize = 1;
+
+/// Runs the work an interrupt handler left pending as `call`.
+pub fn deferred_call(call: usize) {
+    match call {
+        TIMER_CALL => virtual_timer_driver_callback(),
+        CONSOLE_CALL => console_input(),
+        _ => {}
+    }
+}
 
 pub static mut VirtualTimer:
     Option<drivers::timer::VirtualTimer<ast::Ast>> = None;
 
+/// The alarms each process has set
+pub static mut TimerAlarms: Option<Grant<AlarmQueue>> = None;
+
+fn no_alarms() -> AlarmQueue { AlarmQueue::new() }
+
+/// The function each process's alarms call back, or 0 if it has not
+/// subscribed to the timer
+pub static mut TimerCallbacks: Option<Grant<usize>> = None;
+
+fn no_timer_callback() -> usize { 0 }
+
+/// Called by the AST alarm interrupt handler, which has already cleared the
+/// alarm.
+fn timer_interrupt() {
+    unsafe { deferred_call::PENDING.set(TIMER_CALL); }
+}
+
+/// Calls back the process each alarm that went off belongs to, with the
+/// alarm's id in r0.
 pub fn virtual_timer_driver_callback() {
-    let mut vt = unsafe {
-        VirtualTimer.as_mut().expect("VirtualTimer is None!")
+    let (vt, alarms) = unsafe {
+        (VirtualTimer.as_mut().expect("VirtualTimer is None!"),
+         TimerAlarms.as_ref().expect("TimerAlarms is None!"))
     };
 
-    vt.fire_alarm(|&: process_ptr, addr, r0, r1, r2| {
-        let process : &mut process::Process = unsafe { mem::transmute(process_ptr) };
-        process.callbacks.enqueue(
+    vt.fire_alarms(|f: &mut FnMut(&mut AlarmQueue)| alarms.each(|q| f(q)),
+                   |alarm| {
+        let process: &mut process::Process =
+            unsafe { mem::transmute(alarm.client) };
+        process.enqueue_callback(
             process::Callback{
-                pc: addr, r0: r0, r1: r1, r2: r2
+                pc: alarm.callback, r0: alarm.id.0 as usize, r1: 0, r2: 0
             });
     });
 }
 
-pub fn virtual_timer_driver_svc(process_ptr: *mut (), r1: usize, r2: usize) -> isize {
-    let mut vt = unsafe {
-        VirtualTimer.as_mut().expect("VirtualTimer is None!")
+/// Calls back the function at `callback` in `process` after `duration` timer
+/// ticks and, if `periodic`, every `duration` ticks after that. Returns
+/// `None` if the process already has `MAX_ALARMS` alarms set or its grant
+/// region is full, or if `duration` is under `MIN_ALARM_TICKS`.
+pub fn set_timer_alarm(process: &mut Process, duration: u32, callback: usize,
+                       periodic: bool) -> Option<AlarmId> {
+    let (vt, alarms) = unsafe {
+        (VirtualTimer.as_mut().expect("VirtualTimer is None!"),
+         TimerAlarms.as_ref().expect("TimerAlarms is None!"))
     };
+    let process_ptr = process as *mut Process as *mut ();
+    let id = alarms.enter(process, |queue| {
+        vt.set_alarm(queue, process_ptr, callback, duration, periodic)
+    }).unwrap_or(None);
+    // Sets the timer for the new alarm.
+    virtual_timer_driver_callback();
+    id
+}
 
-    vt.set_user_alarm(process_ptr, r1 as u32, r2)
+/// The AST counter extended to 64 bits, so kernel timestamps never wrap
+pub static mut KernelClock: Option<Clock> = None;
+
+/// Called by the AST overflow interrupt handler, which has already cleared the
+/// overflow. Counted right away rather than left to the main loop, as
+/// `clock_ticks` relies on a cleared overflow having been counted.
+fn clock_overflow() {
+    unsafe {
+        KernelClock.as_mut().expect("KernelClock is None!").overflow();
+    }
+}
+
+/// AST ticks since the kernel started.
+pub fn clock_ticks() -> u64 {
+    support::atomic(|| unsafe {
+        KernelClock.as_ref().expect("KernelClock is None!").ticks(&ast::Ast0)
+    })
+}
+
+/// Microseconds since the kernel started.
+pub fn clock_micros() -> u64 {
+    let ticks = clock_ticks();
+    unsafe { KernelClock.as_ref().expect("KernelClock is None!").micros(ticks) }
+}
+
+/// Milliseconds since the kernel started.
+pub fn clock_millis() -> u64 {
+    let ticks = clock_ticks();
+    unsafe { KernelClock.as_ref().expect("KernelClock is None!").millis(ticks) }
+}
+
+/// A process may have `MAX_ALARMS` alarms set at once, kept in its grant
+/// region.
+///
+/// Subscribe 0: the function the process's alarms call back, with the id of
+/// the alarm that went off.
+/// Command 0: sets an alarm that goes off once, after `arg2` ticks. Returns
+/// its id.
+/// Command 1: sets an alarm that goes off every `arg2` ticks. Returns its id.
+/// Alarms shorter than `MIN_ALARM_TICKS` are refused with `EINVAL`.
+/// Command 2: cancels alarm `arg2`.
+/// Command 3: returns the current tick count, which wraps around at 2^32.
+/// It is a raw unsigned word, negative as an `isize` from 2^31 on, not a
+/// `ReturnCode`.
+/// Command 4: returns the number of ticks per second.
+/// Command 5: returns the high 32 bits of the 64-bit tick count since the
+/// kernel started, whose low 32 bits command 3 returns. Also a raw unsigned
+/// word rather than a `ReturnCode`.
+impl<T: Timer> Driver for drivers::timer::VirtualTimer<T> {
+    fn subscribe(&mut self, process: &mut Process,
+                 subscribe_num: usize, callback: usize) -> isize {
+        if subscribe_num != 0 {
+            return ReturnCode::EINVAL.to_isize();
+        }
+        let callbacks = unsafe {
+            TimerCallbacks.as_ref().expect("TimerCallbacks is None!")
+        };
+        match callbacks.enter(process, |cb| *cb = callback) {
+            None => ReturnCode::ENOMEM.to_isize(),
+            Some(()) => ReturnCode::SUCCESS.to_isize()
+        }
+    }
+
+    fn command(&mut self, process: &mut Process,
+               command_num: usize, arg: usize) -> isize {
+        match command_num {
+            0 | 1 => {
+                let callbacks = unsafe {
+                    TimerCallbacks.as_ref().expect("TimerCallbacks is None!")
+                };
+                let callback =
+                    callbacks.enter(process, |cb| *cb).unwrap_or(0);
+                if callback == 0 {
+                    return ReturnCode::EINVAL.to_isize();
+                }
+                let periodic = command_num == 1;
+                match set_timer_alarm(process, arg as u32, callback, periodic) {
+                    None if arg < MIN_ALARM_TICKS as usize =>
+                        ReturnCode::EINVAL.to_isize(),
+                    None => ReturnCode::ENOMEM.to_isize(),
+                    Some(AlarmId(id)) => id as isize
+                }
+            },
+            2 => {
+                let alarms = unsafe {
+                    TimerAlarms.as_ref().expect("TimerAlarms is None!")
+                };
+                let id = AlarmId(arg as u32);
+                if alarms.enter(process, |queue| queue.cancel(id)) !=
+                        Some(true) {
+                    return ReturnCode::EINVAL.to_isize();
+                }
+                // Sets the timer for the alarms that are left.
+                virtual_timer_driver_callback();
+                ReturnCode::SUCCESS.to_isize()
+            },
+            3 => self.now() as isize,
+            4 => self.frequency() as isize,
+            5 => (clock_ticks() >> 32) as isize,
+            _ => ReturnCode::EINVAL.to_isize()
+        }
+    }
 }
 
 pub static mut Console:
     Option<drivers::uart::Console<usart::USART>> = None;
 
-pub fn console_driver_writec_svc(_: *mut (), r1: usize, _: usize) -> isize {
-    let mut console = unsafe {
-        Console.as_mut().expect("Console is None!")
+/// The process subscribed to console input and the function it is called
+/// back with for each byte, if any.
+pub static mut ConsoleReader: Option<(*mut (), usize)> = None;
+
+/// Bytes received by the console interrupt handler and not yet handled by
+/// `console_input`
+pub static mut ConsoleInput: Option<RingBuffer<'static, u8>> = None;
+static mut CONSOLE_INPUT_BUF: [Option<u8>; 32] = [None; 32];
+
+/// Whether console input goes to the kernel shell rather than the process
+/// reading the console. Input goes to the shell anyway while no process reads
+/// the console.
+pub static mut ShellActive: bool = false;
+
+/// Runs the commands typed into the kernel shell. Set by the main loop, which
+/// owns the processes.
+pub static mut SHELL_HOOK: Option<fn(Command)> = None;
+
+static mut SHELL_LINE: LineBuffer = LineBuffer {
+    len: 0, buf: [0; shell::MAX_LINE]
+};
+
+/// Echoes the bytes the console received and calls back the process reading
+/// them, if any, or hands them to the kernel shell.
+fn console_input() {
+    let (console, input) = unsafe {
+        (Console.as_mut().expect("Console is None!"),
+         ConsoleInput.as_mut().expect("ConsoleInput is None!"))
     };
+    loop {
+        let byte = match input.dequeue() {
+            None => { break; },
+            Some(byte) => byte
+        };
+        if byte == shell::TOGGLE_BYTE {
+            unsafe { ShellActive = !ShellActive; }
+            console.putc('\n' as u8);
+            if unsafe { ShellActive } {
+                console.write(shell::PROMPT);
+            }
+            continue;
+        }
+        if unsafe { ShellActive || ConsoleReader.is_none() } {
+            shell_input(console, byte);
+            continue;
+        }
+
+        console.putc(byte);
+        match unsafe { ConsoleReader } {
+            Some((process_ptr, callback)) => {
+                let process: &mut Process =
+                    unsafe { mem::transmute(process_ptr) };
+                process.enqueue_callback(process::Callback {
+                    pc: callback, r0: byte as usize, r1: 0, r2: 0
+                });
+            },
+            None => {}
+        }
+    }
+}
 
-    console.putc(r1 as u8);
-    0
+/// Adds a byte to the shell's command line, and runs the command once the
+/// line is complete.
+fn shell_input(console: &mut drivers::uart::Console<usart::USART>, byte: u8) {
+    let line = unsafe { &mut SHELL_LINE };
+    match line.input(byte) {
+        Input::Echo => console.putc(byte),
+        Input::Erase => console.write("\x08 \x08"),
+        Input::Ignore => {},
+        Input::Done => {
+            console.putc('\n' as u8);
+            match Command::parse(line.line()) {
+                Ok(command) => match unsafe { SHELL_HOOK } {
+                    Some(hook) => hook(command),
+                    None => {}
+                },
+                Err(ParseError::Empty) => {},
+                Err(err) => console.writeln(err.description())
+            }
+            line.clear();
+            console.write(shell::PROMPT);
+        }
+    }
 }
 
-pub fn console_driver_readc_sub(_: *mut (), callback: usize, _: usize) -> isize {
-    let mut console = unsafe {
-        Console.as_mut().expect("Console is None!")
-    };
+/// The buffer each process lent the console to write from, if any. Kept per
+/// process so that processes printing at the same time each write from their
+/// own buffer.
+pub static mut ConsoleWriteBufs: Option<Grant<Option<raw::Slice<u8>>>> = None;
+
+fn no_console_write_buf() -> Option<raw::Slice<u8>> { None }
+
+/// Subscribe: calls back the function at `arg2` with each byte read.
+/// Allow 0: the buffer command 1 writes from.
+/// Command 0: writes the byte `arg2`.
+/// Command 1: writes the first `arg2` bytes of the allowed buffer.
+impl<U: UART> Driver for drivers::uart::Console<U> {
+    fn subscribe(&mut self, process: &mut Process,
+                 _: usize, callback: usize) -> isize {
+        self.read_subscribe();
+        let process_ptr = process as *mut Process as *mut ();
+        unsafe { ConsoleReader = Some((process_ptr, callback)); }
+        ReturnCode::SUCCESS.to_isize()
+    }
 
-    // !! SO very unsafe! See the note at the bottom of this document.
-    let callback_fn: fn(u8) = unsafe { intrinsics::transmute(callback) };
-    console.read_subscribe(callback_fn);
-    0
+    fn command(&mut self, process: &mut Process,
+               command_num: usize, arg: usize) -> isize {
+        match command_num {
+            0 => {
+                self.putc(arg as u8);
+                ReturnCode::SUCCESS.to_isize()
+            },
+            1 => {
+                let bufs = unsafe { ConsoleWriteBufs.as_ref() }
+                    .expect("ConsoleWriteBufs is None!");
+                match bufs.enter(process, |buf| *buf) {
+                    Some(Some(buf)) => {
+                        let buf: &[u8] = unsafe { mem::transmute(buf) };
+                        if arg > buf.len() {
+                            return ReturnCode::ESIZE.to_isize();
+                        }
+                        for &byte in buf[..arg].iter() {
+                            self.putc(byte);
+                        }
+                        ReturnCode::SUCCESS.to_isize()
+                    },
+                    _ => ReturnCode::EINVAL.to_isize()
+                }
+            },
+            _ => ReturnCode::EINVAL.to_isize()
+        }
+    }
+
+    fn allow(&mut self, process: &mut Process, allow_num: usize,
+             buf: &'static mut [u8]) -> isize {
+        if allow_num != 0 {
+            return ReturnCode::EINVAL.to_isize();
+        }
+        let bufs = unsafe {
+            ConsoleWriteBufs.as_ref().expect("ConsoleWriteBufs is None!")
+        };
+        let buf: raw::Slice<u8> = unsafe { mem::transmute(buf) };
+        match bufs.enter(process, |lent| *lent = Some(buf)) {
+            None => ReturnCode::ENOMEM.to_isize(),
+            Some(()) => ReturnCode::SUCCESS.to_isize()
+        }
+    }
 }
 
 pub static mut LED:
     Option<drivers::gpio::LED<gpio::GPIOPin>> = None;
 
-pub fn led_driver_toggle_svc(_: *mut (), _: usize, _: usize) -> isize {
-    let mut led = unsafe {
-        LED.as_mut().expect("LED is None!")
-    };
-
-    led.toggle();
-    0
+/// Command: toggles the LED.
+impl<P: GPIOPin> Driver for drivers::gpio::LED<P> {
+    fn command(&mut self, _: &mut Process, _: usize, _: usize) -> isize {
+        self.toggle();
+        ReturnCode::SUCCESS.to_isize()
+    }
 }
 
 pub static mut TMP006:
@@ -76,37 +377,66 @@ pub static mut TMP006:
 
 // bradjc: this should be temporary until we have a better app<->device driver
 //         interface
-pub fn tmp006_driver_read_svc(_: *mut (), _: usize, _: usize) -> isize {
-    let mut tmp006 = unsafe {
-        TMP006.as_mut().expect("TMP006 is None!")
-    };
+/// Command: reads the temperature.
+impl<I: hil::i2c::I2C> Driver for drivers::i2c::tmp006::TMP006<I> {
+    fn command(&mut self, _: &mut Process, _: usize, _: usize) -> isize {
+        self.read_sync() as isize
+    }
+}
 
-    // return
-    tmp006.read_sync() as isize
+pub static mut IPC: Option<Ipc> = None;
+
+/// Drops every driver subscription, alarm and buffer held by a process, so
+/// nothing calls back into it or touches its memory after it exits or
+/// restarts. State kept in grants goes away with the process's grant region
+/// instead.
+pub fn release_subscriptions(process_ptr: *mut ()) {
+    unsafe {
+        // The console keeps receiving for the kernel shell.
+        let reading = match ConsoleReader {
+            Some((reader, _)) => reader == process_ptr,
+            None => false
+        };
+        if reading {
+            ConsoleReader = None;
+        }
+
+        let process: &mut Process = mem::transmute(process_ptr);
+        let alarms = TimerAlarms.as_ref().expect("TimerAlarms is None!");
+        alarms.enter(process, |queue| queue.clear());
+        // Sets the timer for the alarms that are left.
+        virtual_timer_driver_callback();
+
+        IPC.as_ref().expect("IPC is None!").release(process);
+    }
 }
 
 pub unsafe fn config() {
-    let mut ast = ast::Ast::new(virtual_timer_driver_callback);
+    let mut ast = ast::Ast::new(timer_interrupt, clock_overflow);
     ast.setup();
+    KernelClock = Some(Clock::new(ast.frequency()));
+    ast.enable_ovf_irq();
 
     VirtualTimer = Some(drivers::timer::VirtualTimer::initialize(ast));
-    syscall::SUBSCRIBE_DRIVERS[0] = virtual_timer_driver_svc;
-    syscall::NUM_SUBSCRIBE_DRIVERS += 1;
+    TimerAlarms = Some(Grant::create(no_alarms));
+    TimerCallbacks = Some(Grant::create(no_timer_callback));
+    register(TIMER_DRIVER, VirtualTimer.as_mut().unwrap());
 
+    ConsoleInput = Some(RingBuffer::new(&mut CONSOLE_INPUT_BUF));
+    ConsoleWriteBufs = Some(Grant::create(no_console_write_buf));
     Console = Some(init_console());
-    syscall::CMD_DRIVERS[0] = console_driver_writec_svc;
-    syscall::NUM_CMD_DRIVERS += 1;
-
-    syscall::SUBSCRIBE_DRIVERS[1] = console_driver_readc_sub;
-    syscall::NUM_SUBSCRIBE_DRIVERS += 1;
+    // Always received, for the kernel shell
+    Console.as_mut().unwrap().read_subscribe();
+    register(CONSOLE_DRIVER, Console.as_mut().unwrap());
 
     LED = Some(init_led());
-    syscall::CMD_DRIVERS[1] = led_driver_toggle_svc;
-    syscall::NUM_CMD_DRIVERS += 1;
+    register(LED_DRIVER, LED.as_mut().unwrap());
 
     TMP006 = Some(init_tmp006());
-    syscall::CMD_DRIVERS[2] = tmp006_driver_read_svc;
-    syscall::NUM_CMD_DRIVERS += 1;
+    register(TMP006_DRIVER, TMP006.as_mut().unwrap());
+
+    IPC = Some(Ipc::new());
+    register(IPC_DRIVER, IPC.as_mut().unwrap());
 
     let trng_device = sam4l::trng::TRNGDevice::new(sam4l::trng::TRNGParams {
         location:  sam4l::trng::TRNGLocation::TRNG
@@ -114,11 +444,7 @@ pub unsafe fn config() {
 
     test_trng(trng_device);
 
-    let chipid_device = sam4l::chipid::CHIPIDDevice::new(sam4l::chipid::CHIPIDParams {
-        location:  sam4l::chipid::CHIPIDLocation::CHIPID
-    });
-
-    print_chip_info(chipid_device);
+    chip_info();
 
     let dma_device = sam4l::dma::DMADevice::new(sam4l::dma::DMAParams {
         location: sam4l::dma::DMALocation::DMAChannel00
@@ -181,6 +507,40 @@ pub unsafe fn config() {
     // );
 }
 
+/// Name of driver number `num`, for the kernel shell
+fn driver_name(num: usize) -> &'static str {
+    match num {
+        TIMER_DRIVER => "timer",
+        CONSOLE_DRIVER => "console",
+        LED_DRIVER => "led",
+        TMP006_DRIVER => "tmp006",
+        IPC_DRIVER => "ipc",
+        _ => "unknown"
+    }
+}
+
+/// Prints each registered driver's number and name.
+pub fn print_drivers() {
+    let drivers = unsafe { &syscall::DRIVERS };
+    for entry in drivers.entries[..drivers.len()].iter() {
+        match *entry {
+            Some((num, _)) => {
+                util::print_dec(num as u32);
+                util::print(": ");
+                util::println(driver_name(num));
+            },
+            None => {}
+        }
+    }
+}
+
+/// Makes `driver` available to processes as driver number `num`.
+unsafe fn register(num: usize, driver: &'static mut Driver) {
+    if !syscall::DRIVERS.register(num, driver as *mut Driver) {
+        panic!("Driver number taken or too many drivers");
+    }
+}
+
 fn init_led() -> drivers::gpio::LED<gpio::GPIOPin> {
     use platform::sam4l::gpio;
 
@@ -272,6 +632,15 @@ fn test_trng (mut trng_device: sam4l::trng::TRNGDevice) {
 
 }
 
+/// Prints what the chip identifies itself as.
+pub fn chip_info() {
+    let chipid_device = sam4l::chipid::CHIPIDDevice::new(sam4l::chipid::CHIPIDParams {
+        location:  sam4l::chipid::CHIPIDLocation::CHIPID
+    });
+
+    print_chip_info(chipid_device);
+}
+
 fn print_chip_info (mut chipid_device: sam4l::chipid::CHIPIDDevice) {
     util::println("Information about this microcontroller:");
 
@@ -390,10 +759,10 @@ pub extern fn USART3_Handler() {
         Console.as_mut().expect("Console is None!")
     };
 
-    // This is totally unsafe right now. The UART interrupt handler stored a
-    // pointer to user space and calls it in kernel space. This is definitely
-    // not what should be hapenning! We could use 'task.post', but then we can't
-    // pass parameters to user space. We'll need a better mechanism to invoke
-    // user functions.
-    console.uart_interrupt();
+    // Bytes that arrive while the buffer is full are dropped.
+    let byte = console.uart_interrupt();
+    unsafe {
+        ConsoleInput.as_mut().expect("ConsoleInput is None!").enqueue(byte);
+        deferred_call::PENDING.set(CONSOLE_CALL);
+    }
 }
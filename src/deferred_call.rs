@@ -0,0 +1,82 @@
+use core::prelude::*;
+
+/// Kinds of deferred work there can be
+pub const MAX_DEFERRED_CALLS: usize = 32;
+
+/// Work that interrupt handlers leave for the kernel's main loop, one bit for
+/// each kind. Handlers only mark their work pending; the main loop runs it
+/// before it schedules processes, so process state is never touched from
+/// interrupt context.
+pub struct DeferredCalls {
+    pub pending: u32
+}
+
+impl DeferredCalls {
+    pub fn new() -> DeferredCalls {
+        DeferredCalls { pending: 0 }
+    }
+
+    /// Marks `call` pending. Marking a call that is already pending does
+    /// nothing, so its work runs once for both.
+    pub fn set(&mut self, call: usize) {
+        if call < MAX_DEFERRED_CALLS {
+            self.pending |= 1 << call;
+        }
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.pending != 0
+    }
+
+    /// Clears and returns the lowest numbered pending call.
+    pub fn take(&mut self) -> Option<usize> {
+        for call in range(0, MAX_DEFERRED_CALLS) {
+            if self.pending & (1 << call) != 0 {
+                self.pending &= !(1 << call);
+                return Some(call);
+            }
+        }
+        None
+    }
+}
+
+/// Set from interrupt handlers, so the main loop must mask interrupts around
+/// every other access.
+pub static mut PENDING: DeferredCalls = DeferredCalls { pending: 0 };
+
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use super::{DeferredCalls, MAX_DEFERRED_CALLS};
+
+    #[test]
+    fn takes_lowest_call_first() {
+        let mut calls = DeferredCalls::new();
+        assert!(!calls.has_pending());
+        calls.set(5);
+        calls.set(1);
+        assert!(calls.has_pending());
+        assert_eq!(calls.take(), Some(1));
+        assert_eq!(calls.take(), Some(5));
+        assert_eq!(calls.take(), None);
+        assert!(!calls.has_pending());
+    }
+
+    #[test]
+    fn runs_repeated_call_once() {
+        let mut calls = DeferredCalls::new();
+        calls.set(3);
+        calls.set(3);
+        assert_eq!(calls.take(), Some(3));
+        assert_eq!(calls.take(), None);
+    }
+
+    #[test]
+    fn ignores_unknown_call() {
+        let mut calls = DeferredCalls::new();
+        calls.set(MAX_DEFERRED_CALLS);
+        assert!(!calls.has_pending());
+        calls.set(MAX_DEFERRED_CALLS - 1);
+        assert_eq!(calls.take(), Some(MAX_DEFERRED_CALLS - 1));
+    }
+}
@@ -0,0 +1,33 @@
+use process::Process;
+use syscall::ReturnCode;
+
+/// A device driver processes reach through system calls. Drivers are
+/// registered by number in `syscall::DRIVERS`, and processes pick one with
+/// that number in r0. The other arguments are passed through unchanged.
+///
+/// Every method defaults to refusing with `ENOSUPPORT`, so drivers only
+/// implement the calls they handle. Return `ReturnCode::SUCCESS` or another
+/// nonnegative value on success and a negative `ReturnCode` otherwise.
+pub trait Driver {
+    /// Asks the driver to call back into `process` when something happens.
+    /// The kernel has already checked that `callback` is in the process's
+    /// code. Drivers must only ever run it through
+    /// `Process::enqueue_callback`, never call it themselves.
+    fn subscribe(&mut self, _process: &mut Process,
+                 _arg: usize, _callback: usize) -> isize {
+        ReturnCode::ENOSUPPORT.to_isize()
+    }
+
+    /// Asks the driver to do something right away.
+    fn command(&mut self, _process: &mut Process,
+               _arg1: usize, _arg2: usize) -> isize {
+        ReturnCode::ENOSUPPORT.to_isize()
+    }
+
+    /// Lends the driver `buf`, a part of `process`'s memory, for the use
+    /// numbered `allow_num`.
+    fn allow(&mut self, _process: &mut Process, _allow_num: usize,
+             _buf: &'static mut [u8]) -> isize {
+        ReturnCode::ENOSUPPORT.to_isize()
+    }
+}
@@ -1,88 +1,467 @@
 use core::prelude::*;
-use hil::timer::{AlarmHandler, Timer};
+use hil::timer::Timer;
 
-struct Alarm {
-    armed: bool,
+/// Identifies an alarm so its client can cancel it. Ids are never 0 and
+/// stay below 2^31, so they are positive as an `isize` too.
+#[derive(Copy, PartialEq, Eq, Debug)]
+pub struct AlarmId(pub u32);
+
+/// An alarm in a client's `AlarmQueue`
+#[derive(Copy)]
+pub struct Alarm {
+    pub id: AlarmId,
+    /// When the alarm was set, or when it last went off if it is periodic
     origin: u32,
+    /// Ticks from `origin` until the alarm goes off
     duration: u32,
-    cb_ptr: *mut (),
-    cb_addr: usize
+    /// Ticks between the times a periodic alarm goes off, or 0 for an alarm
+    /// that goes off once
+    period: u32,
+    /// Whose alarm it is, passed back when it goes off
+    pub client: *mut (),
+    pub callback: usize
 }
 
-impl Copy for Alarm {}
+impl Alarm {
+    /// Ticks left at `now` before the alarm goes off. The counter may wrap
+    /// around in the meantime, as long as the alarm is handled less than
+    /// 2^32 ticks after `origin`.
+    fn remaining(&self, now: u32) -> u32 {
+        let elapsed = now.wrapping_sub(self.origin);
+        if elapsed >= self.duration { 0 } else { self.duration - elapsed }
+    }
 
-pub struct VirtualTimer<T: Timer> {
-    timer: T,
-    active: bool,
-    alarms: [Alarm; 10],
+    /// The counter value the alarm goes off at
+    fn deadline(&self) -> u32 {
+        self.origin.wrapping_add(self.duration)
+    }
 }
 
-impl <T: Timer> AlarmHandler for VirtualTimer<T> {
-    fn fire_alarm<F: FnMut(*mut (), usize, usize, usize, usize)>(&mut self, mut post: F) {
-        //let now = self.timer.now();
-        self.timer.disable_alarm();
-        for i in range(0, 10) {
-            let cur = &mut self.alarms[i];
-            if cur.armed {
-                cur.armed = false;
-                post(cur.cb_ptr, cur.cb_addr, 0, 0, 0);
+/// Alarms a client of a `VirtualTimer` may have set at once
+pub const MAX_ALARMS: usize = 4;
+
+/// Shortest alarm a `VirtualTimer` sets. Setting the hardware timer takes a
+/// few ticks, so shorter alarms would come due before the timer is set.
+pub const MIN_ALARM_TICKS: u32 = 4;
+
+/// One client's alarms, in the order they go off. Clients keep their queues
+/// themselves, e.g. the kernel keeps each process's in the process's grant
+/// region, so the timer puts no limit on how many clients there are.
+#[derive(Copy)]
+pub struct AlarmQueue {
+    /// The first `len` entries are used.
+    alarms: [Option<Alarm>; MAX_ALARMS],
+    len: usize
+}
+
+impl AlarmQueue {
+    pub fn new() -> AlarmQueue {
+        AlarmQueue { alarms: [None; MAX_ALARMS], len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Cancels alarm `id`. Returns `false` if it is not in the queue, e.g.
+    /// because it went off already.
+    pub fn cancel(&mut self, id: AlarmId) -> bool {
+        for i in range(0, self.len) {
+            match self.alarms[i] {
+                Some(alarm) if alarm.id == id => {
+                    self.remove(i);
+                    return true;
+                },
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Cancels every alarm.
+    pub fn clear(&mut self) {
+        *self = AlarmQueue::new();
+    }
+
+    fn front(&self) -> Option<Alarm> {
+        if self.len == 0 { None } else { self.alarms[0] }
+    }
+
+    /// Calls `post` with each alarm that has gone off at `now`, in the order
+    /// they went off. Alarms that go off once leave the queue, and periodic
+    /// ones are set for their next time.
+    fn fire<F: FnMut(&Alarm)>(&mut self, now: u32, post: &mut F) {
+        loop {
+            let alarm = match self.front() {
+                Some(alarm) if alarm.remaining(now) == 0 => alarm,
+                _ => { break; }
+            };
+            self.remove(0);
+            if alarm.period != 0 {
+                // Going off at fixed intervals from the first time rather
+                // than from when each was handled keeps the alarm from
+                // drifting. One that fell a whole period behind starts over
+                // from now.
+                let mut next = alarm;
+                next.origin = alarm.deadline();
+                next.duration = alarm.period;
+                if next.remaining(now) == 0 {
+                    next.origin = now;
+                }
+                self.insert(next, now);
+            }
+            (*post)(&alarm);
+        }
+    }
+
+    /// Adds `alarm` behind every alarm that goes off no later than it. Alarms
+    /// keep their order as time passes, so comparing the ticks each has left
+    /// at `now` orders them correctly even when the counter wraps around.
+    /// The queue must not be full.
+    fn insert(&mut self, alarm: Alarm, now: u32) {
+        let remaining = alarm.remaining(now);
+        let mut i = self.len;
+        while i > 0 {
+            match self.alarms[i - 1] {
+                Some(ref other) if other.remaining(now) > remaining => {},
+                _ => { break; }
             }
-            /*let remaining = cur.duration + cur.origin - now;
-            if remaining <= 0 {
-                cur.armed = false;
-                post(cur.cb_addr);
-            }*/
+            self.alarms[i] = self.alarms[i - 1];
+            i -= 1;
+        }
+        self.alarms[i] = Some(alarm);
+        self.len += 1;
+    }
+
+    fn remove(&mut self, index: usize) {
+        for i in range(index, self.len - 1) {
+            self.alarms[i] = self.alarms[i + 1];
         }
+        self.len -= 1;
+        self.alarms[self.len] = None;
     }
 }
 
-impl <T: Timer> VirtualTimer<T> {
+/// Shares one hardware timer between the alarms of any number of clients.
+/// The timer is always set for the soonest alarm at the front of a client's
+/// queue.
+pub struct VirtualTimer<T: Timer> {
+    timer: T,
+    last_id: u32
+}
+
+impl<T: Timer> VirtualTimer<T> {
     pub fn initialize(timer: T) -> VirtualTimer<T> {
-        let base_alarm = Alarm {
-            armed: false, 
-            origin: 0, 
-            duration: 0, 
-            cb_ptr: 0 as *mut (),
-            cb_addr: 0
+        VirtualTimer { timer: timer, last_id: 0 }
+    }
+
+    pub fn now(&self) -> u32 {
+        self.timer.now()
+    }
+
+    /// Ticks per second
+    pub fn frequency(&self) -> u32 {
+        self.timer.frequency()
+    }
+
+    /// Adds an alarm to `client`'s `queue` that goes off after `duration`
+    /// ticks and, if `periodic`, every `duration` ticks after that. Returns
+    /// `None` if the client has `MAX_ALARMS` alarms set already, or if
+    /// `duration` is under `MIN_ALARM_TICKS`. Call `fire_alarms` afterwards
+    /// so the timer goes off in time for it.
+    pub fn set_alarm(&mut self, queue: &mut AlarmQueue, client: *mut (),
+                     callback: usize, duration: u32, periodic: bool)
+            -> Option<AlarmId> {
+        if queue.len >= MAX_ALARMS || duration < MIN_ALARM_TICKS {
+            return None;
+        }
+        self.last_id = if self.last_id >= 0x7fffffff { 1 } else {
+            self.last_id + 1
+        };
+        let now = self.timer.now();
+        let alarm = Alarm {
+            id: AlarmId(self.last_id),
+            origin: now,
+            duration: duration,
+            period: if periodic { duration } else { 0 },
+            client: client,
+            callback: callback
         };
-        VirtualTimer {timer: timer, active: false, alarms: [base_alarm; 10]}
+        queue.insert(alarm, now);
+        Some(alarm.id)
     }
 
-    pub fn set_user_alarm(&mut self, cb_ptr: *mut (), duration: u32, cb: usize) -> isize {
+    /// Calls `post` with each alarm that has gone off, client by client.
+    /// Alarms that go off once leave their queue, and periodic ones are set
+    /// for their next time. Then sets the timer for the soonest alarm left,
+    /// or turns it off if there is none. `each_queue` must pass every
+    /// client's queue to the function it is given.
+    pub fn fire_alarms<E, F>(&mut self, mut each_queue: E, mut post: F)
+            where E: FnMut(&mut FnMut(&mut AlarmQueue)),
+                  F: FnMut(&Alarm) {
         let now = self.timer.now();
-        let alarm = Alarm { armed: true,
-                            origin: now,
-                            duration: duration,
-                            cb_ptr: cb_ptr,
-                            cb_addr: cb
-                          };
-        if !self.add_alarm(alarm) {
-            return -1;
-        }
-        if !self.active {
-            let mut min_remaining = alarm.duration + alarm.origin - now;
-            for i in range(0, 10) {
-                let cur = self.alarms[i];
-                let elapsed = now - cur.origin;
-                let remaining = cur.duration - elapsed;
-                if remaining < min_remaining {
-                    min_remaining = remaining;
-                }
+        let mut soonest: Option<u32> = None;
+        each_queue(&mut |queue: &mut AlarmQueue| {
+            queue.fire(now, &mut post);
+            let remaining = match queue.front() {
+                None => { return; },
+                Some(alarm) => alarm.remaining(now)
+            };
+            soonest = match soonest {
+                Some(ticks) if ticks <= remaining => Some(ticks),
+                _ => Some(remaining)
+            };
+        });
+
+        let (mut start, mut remaining) = match soonest {
+            None => {
+                self.timer.disable_alarm();
+                return;
+            },
+            Some(remaining) => (now, remaining)
+        };
+        loop {
+            self.timer.set_alarm(start.wrapping_add(remaining));
+            // The counter may pass the deadline before the timer is set for
+            // it, in which case the timer never goes off. The timer is then
+            // set further ahead rather than the alarms that came due being
+            // fired here, which would never end for periodic alarms shorter
+            // than the time setting the timer takes.
+            let elapsed = self.timer.now().wrapping_sub(start);
+            if elapsed < remaining {
+                break;
             }
-            let next_alarm = now + min_remaining;
-            self.timer.set_alarm(next_alarm);
+            start = start.wrapping_add(elapsed);
+            remaining = elapsed.saturating_mul(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use core::cell::Cell;
+    use hil::timer::Timer;
+    use super::{AlarmId, AlarmQueue, MAX_ALARMS, MIN_ALARM_TICKS,
+                VirtualTimer};
+
+    /// The state of a `MockTimer`, which the test moves by hand
+    struct Mock {
+        now: Cell<u32>,
+        alarm: Cell<Option<u32>>,
+        /// Ticks that pass each time the timer is set
+        set_delay: Cell<u32>
+    }
+
+    impl Mock {
+        fn new(now: u32) -> Mock {
+            Mock { now: Cell::new(now), alarm: Cell::new(None),
+                   set_delay: Cell::new(0) }
         }
-        return 0;
     }
 
-    fn add_alarm(&mut self, alarm: Alarm) -> bool {
-        for i in range(0, 10) {
-            if !self.alarms[i].armed {
-                self.alarms[i] = alarm;
-                return true;
+    struct MockTimer<'a> {
+        mock: &'a Mock
+    }
+
+    impl<'a> Timer for MockTimer<'a> {
+        fn now(&self) -> u32 {
+            self.mock.now.get()
+        }
+
+        fn frequency(&self) -> u32 {
+            1000
+        }
+
+        fn set_alarm(&mut self, tics: u32) {
+            self.mock.alarm.set(Some(tics));
+            let now = self.mock.now.get();
+            self.mock.now.set(now + self.mock.set_delay.get());
+        }
+
+        fn disable_alarm(&mut self) {
+            self.mock.alarm.set(None);
+        }
+    }
+
+    const CLIENT: *mut () = 1 as *mut ();
+
+    fn timer(mock: &Mock) -> VirtualTimer<MockTimer> {
+        VirtualTimer::initialize(MockTimer { mock: mock })
+    }
+
+    /// Fires the alarms in `queues` that went off and returns their
+    /// callbacks, in order.
+    fn fire(vt: &mut VirtualTimer<MockTimer>, queues: &mut [AlarmQueue])
+            -> Vec<usize> {
+        let mut fired = Vec::new();
+        vt.fire_alarms(|f: &mut FnMut(&mut AlarmQueue)| {
+            for queue in queues.iter_mut() {
+                f(queue);
             }
+        }, |alarm| fired.push(alarm.callback));
+        fired
+    }
+
+    #[test]
+    fn fires_only_expired_alarms_in_order() {
+        let mock = Mock::new(100);
+        let mut vt = timer(&mock);
+        let mut queues = [AlarmQueue::new()];
+        vt.set_alarm(&mut queues[0], CLIENT, 30, 30, false);
+        vt.set_alarm(&mut queues[0], CLIENT, 10, 10, false);
+        vt.set_alarm(&mut queues[0], CLIENT, 20, 20, false);
+        assert_eq!(fire(&mut vt, &mut queues), vec![]);
+        assert_eq!(mock.alarm.get(), Some(110));
+
+        mock.now.set(125);
+        assert_eq!(fire(&mut vt, &mut queues), vec![10, 20]);
+        assert_eq!(mock.alarm.get(), Some(130));
+
+        mock.now.set(130);
+        assert_eq!(fire(&mut vt, &mut queues), vec![30]);
+        assert_eq!(mock.alarm.get(), None);
+        assert_eq!(fire(&mut vt, &mut queues), vec![]);
+    }
+
+    #[test]
+    fn sets_timer_for_soonest_alarm_of_any_client() {
+        let mock = Mock::new(0);
+        let mut vt = timer(&mock);
+        let mut queues = [AlarmQueue::new(), AlarmQueue::new()];
+        vt.set_alarm(&mut queues[0], CLIENT, 1, 30, false);
+        vt.set_alarm(&mut queues[1], CLIENT, 2, 20, false);
+        vt.set_alarm(&mut queues[1], CLIENT, 3, 40, false);
+        assert_eq!(fire(&mut vt, &mut queues), vec![]);
+        assert_eq!(mock.alarm.get(), Some(20));
+
+        mock.now.set(35);
+        assert_eq!(fire(&mut vt, &mut queues), vec![1, 2]);
+        assert_eq!(mock.alarm.get(), Some(40));
+        assert_eq!((queues[0].len(), queues[1].len()), (0, 1));
+    }
+
+    #[test]
+    fn handles_counter_wraparound() {
+        let mock = Mock::new(0xffff_fff0);
+        let mut vt = timer(&mock);
+        let mut queues = [AlarmQueue::new()];
+        vt.set_alarm(&mut queues[0], CLIENT, 1, 0x20, false);
+        vt.set_alarm(&mut queues[0], CLIENT, 2, 0x08, false);
+        assert_eq!(fire(&mut vt, &mut queues), vec![]);
+        assert_eq!(mock.alarm.get(), Some(0xffff_fff8));
+
+        mock.now.set(0xffff_fffc);
+        assert_eq!(fire(&mut vt, &mut queues), vec![2]);
+
+        mock.now.set(0x0000_0004);
+        assert_eq!(fire(&mut vt, &mut queues), vec![]);
+        assert_eq!(mock.alarm.get(), Some(0x0000_0010));
+
+        mock.now.set(0x0000_0010);
+        assert_eq!(fire(&mut vt, &mut queues), vec![1]);
+    }
+
+    #[test]
+    fn periodic_alarm_keeps_its_period() {
+        let mock = Mock::new(0);
+        let mut vt = timer(&mock);
+        let mut queues = [AlarmQueue::new()];
+        assert_eq!(vt.set_alarm(&mut queues[0], CLIENT, 1, 0, true), None);
+        assert_eq!(vt.set_alarm(&mut queues[0], CLIENT, 1,
+                                MIN_ALARM_TICKS - 1, false), None);
+        vt.set_alarm(&mut queues[0], CLIENT, 1, 100, true);
+
+        // Handled late, but the next one is still due at 200.
+        mock.now.set(105);
+        assert_eq!(fire(&mut vt, &mut queues), vec![1]);
+        assert_eq!(mock.alarm.get(), Some(200));
+
+        // More than a period behind: starts over from now.
+        mock.now.set(450);
+        assert_eq!(fire(&mut vt, &mut queues), vec![1]);
+        assert_eq!(mock.alarm.get(), Some(550));
+    }
+
+    #[test]
+    fn cancels_alarms() {
+        let mock = Mock::new(0);
+        let mut vt = timer(&mock);
+        let mut queues = [AlarmQueue::new(), AlarmQueue::new()];
+        let first = vt.set_alarm(&mut queues[0], CLIENT, 1, 10, false)
+                      .unwrap();
+        vt.set_alarm(&mut queues[1], CLIENT, 2, 20, true);
+        vt.set_alarm(&mut queues[0], CLIENT, 3, 30, true);
+        assert_eq!(queues[0].len(), 2);
+
+        assert!(!queues[1].cancel(first));
+        assert!(queues[0].cancel(first));
+        assert!(!queues[0].cancel(first));
+        assert!(!queues[0].cancel(AlarmId(1234)));
+        assert_eq!(fire(&mut vt, &mut queues), vec![]);
+        assert_eq!(mock.alarm.get(), Some(20));
+
+        queues[1].clear();
+        assert_eq!(queues[1].len(), 0);
+        assert_eq!(fire(&mut vt, &mut queues), vec![]);
+        assert_eq!(mock.alarm.get(), Some(30));
+
+        mock.now.set(30);
+        assert_eq!(fire(&mut vt, &mut queues), vec![3]);
+    }
+
+    #[test]
+    fn refuses_alarms_when_full() {
+        let mock = Mock::new(0);
+        let mut vt = timer(&mock);
+        let mut queue = AlarmQueue::new();
+        let mut ids = Vec::new();
+        for i in range(0, MAX_ALARMS) {
+            ids.push(vt.set_alarm(&mut queue, CLIENT, i, 10, false).unwrap());
         }
-        return false;
+        assert!(ids[0] != ids[1]);
+        assert_eq!(vt.set_alarm(&mut queue, CLIENT, 9, 10, false), None);
+
+        // The queue being full does not stop other clients' alarms.
+        let mut other = AlarmQueue::new();
+        assert!(vt.set_alarm(&mut other, CLIENT, 9, 10, false).is_some());
     }
-}
 
+    #[test]
+    fn sets_timer_again_for_alarm_that_came_due_while_setting_it() {
+        let mock = Mock::new(0);
+        let mut vt = timer(&mock);
+        let mut queues = [AlarmQueue::new()];
+        vt.set_alarm(&mut queues[0], CLIENT, 1, 10, false);
+        vt.set_alarm(&mut queues[0], CLIENT, 2, 12, false);
+
+        // Setting the timer for the second alarm takes past its deadline, so
+        // the timer is set again, twice as far ahead as setting it took.
+        mock.set_delay.set(5);
+        mock.now.set(10);
+        assert_eq!(fire(&mut vt, &mut queues), vec![1]);
+        assert_eq!(mock.alarm.get(), Some(25));
+
+        mock.now.set(25);
+        assert_eq!(fire(&mut vt, &mut queues), vec![2]);
+        assert_eq!(mock.alarm.get(), None);
+    }
+
+    #[test]
+    fn periodic_alarm_shorter_than_setting_timer_fires_once_per_call() {
+        let mock = Mock::new(0);
+        let mut vt = timer(&mock);
+        let mut queues = [AlarmQueue::new()];
+        vt.set_alarm(&mut queues[0], CLIENT, 1, MIN_ALARM_TICKS, true);
+
+        mock.set_delay.set(MIN_ALARM_TICKS * 3);
+        mock.now.set(MIN_ALARM_TICKS);
+        assert_eq!(fire(&mut vt, &mut queues), vec![1]);
+        let alarm = mock.alarm.get().unwrap();
+        assert!(alarm.wrapping_sub(mock.now.get()) < 0x8000_0000);
+
+        mock.now.set(alarm);
+        assert_eq!(fire(&mut vt, &mut queues), vec![1]);
+        assert_eq!(queues[0].len(), 1);
+    }
+}
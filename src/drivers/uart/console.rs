@@ -10,7 +10,7 @@ pub struct ConsoleParams {
 
 pub struct Console<T: UART> {
     uart: T,
-    read_callback: Option<fn(u8)>
+    reading: bool
 }
 
 impl<T: UART> Console<T> {
@@ -24,28 +24,25 @@ impl<T: UART> Console<T> {
         uart.toggle_tx(true);
         Console {
             uart: uart,
-            read_callback: None
+            reading: false
         }
     }
 
-    pub fn uart_interrupt(&mut self) {
-        let byte = self.uart.read_byte();
-        self.putc(byte);
-        // if let Some(ref callback) = self.read_callback {
-        //     callback(self.uart.read_byte());
-        // }
+    /// Takes the byte that was just received, which clears the interrupt.
+    pub fn uart_interrupt(&mut self) -> u8 {
+        self.uart.read_byte()
     }
 
     pub fn putc(&mut self, byte: u8) {
         self.uart.send_byte(byte);
     }
 
-    pub fn read_subscribe(&mut self, callback: fn(u8)) {
-        if self.read_callback.is_none() {
+    pub fn read_subscribe(&mut self) {
+        if !self.reading {
             self.uart.toggle_rx(true);
         }
 
-        self.read_callback = Some(callback);
+        self.reading = true;
     }
 
     pub fn write(&mut self, content: &str) {
@@ -0,0 +1,150 @@
+use core::prelude::*;
+use core::fmt::{self, Write};
+use platform::cortex::scb::SCB;
+use process::{Process, State};
+use util;
+
+/// Names of the Configurable Fault Status Register bits
+static CFSR_BITS: [(usize, &'static str); 19] = [
+    // MemManage
+    (0, "IACCVIOL"), (1, "DACCVIOL"), (3, "MUNSTKERR"), (4, "MSTKERR"),
+    (5, "MLSPERR"), (7, "MMARVALID"),
+    // BusFault
+    (8, "IBUSERR"), (9, "PRECISERR"), (10, "IMPRECISERR"), (11, "UNSTKERR"),
+    (12, "STKERR"), (13, "LSPERR"), (15, "BFARVALID"),
+    // UsageFault
+    (16, "UNDEFINSTR"), (17, "INVSTATE"), (18, "INVPC"), (19, "NOCP"),
+    (24, "UNALIGNED"), (25, "DIVBYZERO")
+];
+
+/// Names of the HardFault Status Register bits
+static HFSR_BITS: [(usize, &'static str); 3] = [
+    (1, "VECTTBL"), (30, "FORCED"), (31, "DEBUGEVT")
+];
+
+/// Names of the registers the hardware stacks on exception entry, in order
+static FRAME_REGS: [&'static str; 8] = [
+    "r0", "r1", "r2", "r3", "r12", "lr", "pc", "xPSR"
+];
+
+/// Words of the process stack printed above the exception frame
+const STACK_DUMP_WORDS: isize = 8;
+
+/// Stops a process that faulted from ever running again and prints what
+/// went wrong. The rest of the system keeps going.
+pub fn process_fault(process: &mut Process) {
+    process.state = State::Faulted;
+
+    util::print("\nProcess ");
+    util::print(process.name);
+    util::print(" (slot ");
+    util::print_dec(process.slot as u32);
+    util::println(") faulted");
+
+    let scb = unsafe { SCB::get() };
+    let frame = process.cur_stack as *const usize;
+    let stack_top = process.memory.as_ptr() as usize + process.memory.len();
+    if (frame as usize) < (process.stack_limit() as usize) ||
+            (frame as usize) + 8 * 4 > stack_top {
+        // Stack overflow, or the hardware could not stack the frame at all.
+        util::print("  Stack pointer ");
+        util::print_hex(frame as usize);
+        util::println(" is outside the process's memory");
+    } else {
+        dump_frame(frame);
+        dump_stack(frame, stack_top);
+    }
+    dump_fault_status(scb);
+
+    scb.clear_faults();
+}
+
+/// Called by the fault handlers in `ctx_switch.S` when the kernel itself
+/// faults. `stack` points to the exception frame stacked by the hardware.
+#[no_mangle]
+pub unsafe extern fn kernel_fault(stack: *const usize) -> ! {
+    util::println("\nKernel fault");
+    dump_frame(stack);
+    dump_fault_status(SCB::get());
+    panic!("Kernel fault");
+}
+
+/// Prints kernel panics on the console before the system halts. Installed
+/// as `support::PANIC_HOOK` once the console is up.
+pub fn panic_hook(fmt: &fmt::Arguments, file_line: &(&'static str, usize)) {
+    let &(file, line) = file_line;
+    util::print("\nKernel panic at ");
+    util::print(file);
+    util::print(":");
+    util::print_dec(line as u32);
+    util::print(": ");
+    let _ = util::ConsoleWriter.write_fmt(*fmt);
+    util::println("");
+}
+
+/// Prints the registers the hardware stacked at `frame`.
+fn dump_frame(frame: *const usize) {
+    for i in range(0, FRAME_REGS.len()) {
+        let val = unsafe { *frame.offset(i as isize) };
+        util::print("  ");
+        util::print(FRAME_REGS[i]);
+        util::print(": ");
+        util::print_hex(val);
+        util::println("");
+    }
+}
+
+/// Prints the top of the stack that was in use before the exception,
+/// stopping at the end of the process's memory.
+fn dump_stack(frame: *const usize, stack_top: usize) {
+    util::print("  Stack pointer: ");
+    util::print_hex(frame as usize + 8 * 4);
+    util::println("");
+
+    for i in range(8, 8 + STACK_DUMP_WORDS) {
+        let addr = unsafe { frame.offset(i) };
+        if addr as usize + 4 > stack_top {
+            break;
+        }
+        util::print("    ");
+        util::print_hex(addr as usize);
+        util::print(": ");
+        util::print_hex(unsafe { *addr });
+        util::println("");
+    }
+}
+
+fn dump_fault_status(scb: &SCB) {
+    let cfsr = scb.cfsr();
+    let hfsr = scb.hfsr();
+
+    util::print("  CFSR: ");
+    util::print_hex(cfsr);
+    print_bit_names(cfsr, &CFSR_BITS);
+    util::println("");
+
+    util::print("  HFSR: ");
+    util::print_hex(hfsr);
+    print_bit_names(hfsr, &HFSR_BITS);
+    util::println("");
+
+    if cfsr & (1 << 7) != 0 {
+        util::print("  MMFAR: ");
+        util::print_hex(scb.mmfar());
+        util::println("");
+    }
+    if cfsr & (1 << 15) != 0 {
+        util::print("  BFAR: ");
+        util::print_hex(scb.bfar());
+        util::println("");
+    }
+}
+
+fn print_bit_names(val: usize, names: &[(usize, &'static str)]) {
+    for &(bit, name) in names.iter() {
+        if val & (1 << bit) != 0 {
+            util::print(" ");
+            util::print(name);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use core::mem;
+use core::prelude::*;
+use core::ptr;
+
+use grant_region::MAX_GRANTS;
+use process::{self, Process};
+use slot_allocator::NUM_SLOTS;
+
+/// Grants created so far, which is also the id of the next one.
+static mut NUM_GRANTS: usize = 0;
+
+/// State a driver keeps for each process, allocated in the grant region at
+/// the top of the process's own memory rather than in a fixed size table in
+/// the driver. A driver can have as many clients as there are processes.
+///
+/// The state is allocated the first time the driver enters the grant for a
+/// process, and is thrown away without being dropped when the process
+/// restarts or stops for good. Only the kernel's main loop may use grants,
+/// never interrupt handlers.
+pub struct Grant<T: Copy> {
+    id: usize,
+    init: fn() -> T
+}
+
+impl<T: Copy> Grant<T> {
+    /// Creates a grant whose state in each process starts out as `init()`.
+    /// Panics if more than `MAX_GRANTS` grants are created.
+    pub unsafe fn create(init: fn() -> T) -> Grant<T> {
+        if NUM_GRANTS >= MAX_GRANTS {
+            panic!("Too many grants");
+        }
+        NUM_GRANTS += 1;
+        Grant { id: NUM_GRANTS - 1, init: init }
+    }
+
+    /// Calls `f` with the state for `process`, allocating it first if need
+    /// be. Returns `None` if the process's grant region is full.
+    pub fn enter<R, F: FnOnce(&mut T) -> R>(&self, process: &mut Process,
+                                            f: F) -> Option<R> {
+        let state = {
+            let mut region = process.grant_region();
+            match region.get(self.id) {
+                Some(ptr) => Some(ptr as *mut T),
+                None => {
+                    let state = (self.init)();
+                    region.alloc(self.id, mem::size_of::<T>(),
+                                 mem::min_align_of::<T>()).map(|ptr| {
+                        let ptr = ptr as *mut T;
+                        unsafe { ptr::write(ptr, state); }
+                        ptr
+                    })
+                }
+            }
+        };
+        state.map(|ptr| f(unsafe { &mut *ptr }))
+    }
+
+    /// Calls `f` with the state for `process` if it has one, without
+    /// allocating it. Returns `None` if it has none.
+    pub fn get<R, F: FnOnce(&mut T) -> R>(&self, process: &mut Process,
+                                          f: F) -> Option<R> {
+        let state = process.grant_region().get(self.id);
+        state.map(|ptr| f(unsafe { &mut *(ptr as *mut T) }))
+    }
+
+    /// Calls `f` with the state of every process that has one.
+    pub fn each<F: FnMut(&mut T)>(&self, mut f: F) {
+        for slot in range(0, NUM_SLOTS) {
+            let state = process::grant_region(slot).and_then(|region| {
+                region.get(self.id)
+            });
+            match state {
+                Some(ptr) => f(unsafe { &mut *(ptr as *mut T) }),
+                None => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use core::mem;
+use core::prelude::*;
+
+/// Most grants drivers can create
+pub const MAX_GRANTS: usize = 8;
+
+/// Bytes at the top of a grant region taken by its bookkeeping: the number
+/// of bytes in use followed by one entry for each grant.
+const TABLE_SIZE: usize = (MAX_GRANTS + 1) * 4;
+
+/// Memory in a process's slot that drivers allocate their state for that
+/// process in. Each grant gets at most one allocation, found through a table
+/// at the top of the region. Allocations are made downward from below the
+/// table and are only given back all at once, by `clear`.
+///
+/// The table lives in the region itself, so any `GrantRegion` over the same
+/// memory sees the same allocations. The end of the memory must be word
+/// aligned.
+pub struct GrantRegion<'a> {
+    memory: &'a mut [u8]
+}
+
+impl<'a> GrantRegion<'a> {
+    pub fn new(memory: &'a mut [u8]) -> GrantRegion<'a> {
+        GrantRegion { memory: memory }
+    }
+
+    /// Throws away every allocation.
+    pub fn clear(&mut self) {
+        for i in range(0, MAX_GRANTS + 1) {
+            self.set_entry(i, 0);
+        }
+    }
+
+    /// Bytes in use, including the table.
+    pub fn used(&self) -> usize {
+        let used = self.entry(0);
+        if used < TABLE_SIZE { TABLE_SIZE } else { used }
+    }
+
+    /// The memory allocated for grant `id`, if any.
+    pub fn get(&self, id: usize) -> Option<*mut u8> {
+        if id >= MAX_GRANTS {
+            return None;
+        }
+        match self.entry(id + 1) {
+            0 => None,
+            offset => Some((self.top() - offset) as *mut u8)
+        }
+    }
+
+    /// Allocates `size` bytes aligned to `align`, a power of two, for grant
+    /// `id`. Returns `None` if the grant already has memory or the region is
+    /// full.
+    pub fn alloc(&mut self, id: usize, size: usize, align: usize)
+            -> Option<*mut u8> {
+        if id >= MAX_GRANTS || self.get(id).is_some() {
+            return None;
+        }
+        let base = self.memory.as_ptr() as usize;
+        let free_end = self.top() - self.used();
+        if size > free_end - base {
+            return None;
+        }
+        let start = (free_end - size) & !(align - 1);
+        if start < base {
+            return None;
+        }
+        let offset = self.top() - start;
+        self.set_entry(0, offset);
+        self.set_entry(id + 1, offset);
+        Some(start as *mut u8)
+    }
+
+    fn top(&self) -> usize {
+        self.memory.as_ptr() as usize + self.memory.len()
+    }
+
+    fn entry_ptr(&self, i: usize) -> *mut u32 {
+        (self.top() - TABLE_SIZE + i * mem::size_of::<u32>()) as *mut u32
+    }
+
+    fn entry(&self, i: usize) -> usize {
+        unsafe { *self.entry_ptr(i) as usize }
+    }
+
+    fn set_entry(&mut self, i: usize, val: usize) {
+        unsafe { *self.entry_ptr(i) = val as u32; }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem;
+    use core::prelude::*;
+    use super::{GrantRegion, MAX_GRANTS, TABLE_SIZE};
+
+    fn with_region<F: FnOnce(GrantRegion)>(f: F) {
+        let mut words = [0xffffffffu32; 64];
+        let bytes: &mut [u8; 256] = unsafe { mem::transmute(&mut words) };
+        let mut region = GrantRegion::new(bytes);
+        region.clear();
+        f(region);
+    }
+
+    #[test]
+    fn starts_empty() {
+        with_region(|region| {
+            assert_eq!(region.used(), TABLE_SIZE);
+            for id in range(0, MAX_GRANTS) {
+                assert_eq!(region.get(id), None);
+            }
+        });
+    }
+
+    #[test]
+    fn allocates_below_table() {
+        with_region(|mut region| {
+            let top = region.memory.as_ptr() as usize + 256;
+            let a = region.alloc(0, 12, 4).unwrap() as usize;
+            assert_eq!(a, top - TABLE_SIZE - 12);
+            let b = region.alloc(3, 6, 8).unwrap() as usize;
+            assert_eq!(b % 8, 0);
+            assert!(b + 6 <= a);
+            assert_eq!(region.get(0), Some(a as *mut u8));
+            assert_eq!(region.get(3), Some(b as *mut u8));
+            assert_eq!(region.get(1), None);
+            assert_eq!(region.used(), top - b);
+        });
+    }
+
+    #[test]
+    fn allocates_each_grant_once() {
+        with_region(|mut region| {
+            assert!(region.alloc(2, 4, 4).is_some());
+            assert_eq!(region.alloc(2, 4, 4), None);
+            assert_eq!(region.alloc(MAX_GRANTS, 4, 4), None);
+        });
+    }
+
+    #[test]
+    fn refuses_when_full() {
+        with_region(|mut region| {
+            assert_eq!(region.alloc(0, 256 - TABLE_SIZE + 1, 1), None);
+            assert!(region.alloc(0, 256 - TABLE_SIZE, 1).is_some());
+            assert_eq!(region.alloc(1, 1, 1), None);
+        });
+    }
+
+    #[test]
+    fn clear_frees_everything() {
+        with_region(|mut region| {
+            assert!(region.alloc(0, 200, 4).is_some());
+            region.clear();
+            assert_eq!(region.get(0), None);
+            assert!(region.alloc(1, 200, 4).is_some());
+        });
+    }
+}
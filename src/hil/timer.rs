@@ -2,10 +2,14 @@ use core::prelude::*;
 
 pub trait Timer {
     fn now(&self) -> u32;
+    /// Ticks per second
+    fn frequency(&self) -> u32;
     fn set_alarm(&mut self, u32);
     fn disable_alarm(&mut self);
 }
 
-pub trait AlarmHandler {
-    fn fire_alarm<F: FnMut(*mut (), usize, usize, usize, usize)>(&mut self, F);
+/// A timer that notes when its counter wraps around to 0.
+pub trait OverflowTimer: Timer {
+    /// Whether the counter wrapped around since the overflow was last cleared.
+    fn overflow_pending(&self) -> bool;
 }
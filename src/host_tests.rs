@@ -0,0 +1,188 @@
+//! Host-side unit tests for the parts of the kernel that do not touch
+//! hardware. Build and run with `make test`.
+
+#![feature(core,std_misc)]
+#![allow(dead_code)]
+
+extern crate core;
+
+#[path = "app_header.rs"]
+mod app_header;
+
+// The virtual timer and the kernel clock are tested against mocks of the
+// timer interfaces in hil.
+#[path = "hil/timer.rs"]
+mod hil_timer;
+
+mod hil {
+    pub use hil_timer as timer;
+}
+
+#[path = "drivers/timer.rs"]
+mod timer;
+
+#[path = "clock.rs"]
+mod clock;
+
+#[path = "array_list.rs"]
+mod array_list;
+
+#[path = "deferred_call.rs"]
+mod deferred_call;
+
+#[path = "grant_region.rs"]
+mod grant_region;
+
+// IPC is tested against a mock of the processes it serves, whose grant
+// regions are real.
+#[path = "grant.rs"]
+mod grant;
+
+#[path = "driver.rs"]
+mod driver;
+
+#[path = "ipc.rs"]
+mod ipc;
+
+mod process {
+    use core::prelude::*;
+    use core::mem;
+    use core::raw;
+    use grant_region::GrantRegion;
+    use slot_allocator::NUM_SLOTS;
+    use syscall::ReturnCode;
+
+    const GRANT_MEMORY_SIZE: usize = 256;
+
+    static mut GRANT_MEMORIES: [[u32; GRANT_MEMORY_SIZE / 4]; NUM_SLOTS] =
+        [[0; GRANT_MEMORY_SIZE / 4]; NUM_SLOTS];
+    static mut USED: [bool; NUM_SLOTS] = [false; NUM_SLOTS];
+
+    fn grant_memory(slot: usize) -> &'static mut [u8] {
+        unsafe {
+            mem::transmute(raw::Slice {
+                data: GRANT_MEMORIES[slot].as_ptr() as *const u8,
+                len: GRANT_MEMORY_SIZE
+            })
+        }
+    }
+
+    pub fn grant_region(slot: usize) -> Option<GrantRegion<'static>> {
+        if slot >= NUM_SLOTS || unsafe { !USED[slot] } {
+            return None;
+        }
+        Some(GrantRegion::new(grant_memory(slot)))
+    }
+
+    #[derive(Copy)]
+    pub struct Callback {
+        pub r0: usize,
+        pub r1: usize,
+        pub r2: usize,
+        pub pc: usize
+    }
+
+    pub struct SharedBuffer {
+        addr: usize,
+        len: usize
+    }
+
+    impl SharedBuffer {
+        pub fn addr(&self) -> usize { self.addr }
+        pub fn len(&self) -> usize { self.len }
+    }
+
+    pub struct Process<'a> {
+        pub name: &'static str,
+        pub slot: usize,
+        grant_memory: &'a mut [u8],
+        /// Callbacks enqueued so far
+        pub callbacks: Vec<Callback>,
+        /// Owner slot, address and length of each buffer shared with the
+        /// process
+        pub shared: Vec<(usize, usize, usize)>
+    }
+
+    impl<'a> Process<'a> {
+        /// A process in memory slot `slot` with an empty grant region
+        pub fn new(name: &'static str, slot: usize) -> Process<'static> {
+            unsafe { USED[slot] = true; }
+            let mut process = Process {
+                name: name, slot: slot, grant_memory: grant_memory(slot),
+                callbacks: Vec::new(), shared: Vec::new()
+            };
+            process.grant_region().clear();
+            process
+        }
+
+        pub fn grant_region(&mut self) -> GrantRegion {
+            GrantRegion::new(&mut *self.grant_memory)
+        }
+
+        pub fn enqueue_callback(&mut self, callback: Callback) -> bool {
+            self.callbacks.push(callback);
+            true
+        }
+
+        pub fn share_buffer(&mut self, owner_slot: usize, buf: &[u8])
+                -> Result<(), ReturnCode> {
+            self.shared.push((owner_slot, buf.as_ptr() as usize, buf.len()));
+            Ok(())
+        }
+
+        pub fn shared_buffer(&self, owner_slot: usize) -> Option<SharedBuffer> {
+            self.shared.iter().find(|&&(owner, _, _)| owner == owner_slot)
+                .map(|&(_, addr, len)| SharedBuffer { addr: addr, len: len })
+        }
+
+        pub fn unshare_buffer(&mut self, owner_slot: usize) {
+            self.shared.retain(|&(owner, _, _)| owner != owner_slot);
+        }
+    }
+}
+
+mod syscall {
+    /// The return codes drivers use, as in syscall.rs
+    #[derive(Copy, PartialEq, Eq, Debug)]
+    pub enum ReturnCode {
+        SUCCESS = 0,
+        EBUSY = -2,
+        EINVAL = -3,
+        ENOMEM = -4,
+        ENODEVICE = -6,
+        ENOSUPPORT = -7,
+        EPERM = -8,
+    }
+
+    impl ReturnCode {
+        pub fn to_isize(self) -> isize {
+            self as isize
+        }
+    }
+}
+
+#[path = "mpu_region.rs"]
+mod mpu_region;
+
+#[path = "registry.rs"]
+mod registry;
+
+#[path = "ring_buffer.rs"]
+mod ring_buffer;
+
+#[path = "scheduler.rs"]
+mod scheduler;
+
+#[path = "shell.rs"]
+mod shell;
+
+#[path = "slot_allocator.rs"]
+mod slot_allocator;
+
+#[path = "stats.rs"]
+mod stats;
+
+// The kernel's queues use critical sections from the support crate, which
+// simulates interrupt masking on the host.
+#[path = "support/critical_section.rs"]
+mod support;
@@ -0,0 +1,251 @@
+use core::mem;
+use core::prelude::*;
+
+use driver::Driver;
+use grant::Grant;
+use process::{Callback, Process};
+use syscall::ReturnCode;
+
+/// What a process uses IPC for. Only subscribing creates it, so every
+/// process that has one has its `process` set.
+#[derive(Copy)]
+struct IpcState {
+    /// The process, so other processes can find it
+    process: *mut (),
+    /// Called when a client notifies the process, or 0 if the process does
+    /// not provide a service.
+    service_callback: usize,
+    /// Called when a service notifies the process, or 0.
+    client_callback: usize,
+    /// Bit `n` set if the process in memory slot `n` notified this one as a
+    /// service or shared a buffer with it. This process may only notify
+    /// those processes as clients.
+    clients: u32
+}
+
+fn new_state() -> IpcState {
+    IpcState {
+        process: 0 as *mut (),
+        service_callback: 0,
+        client_callback: 0,
+        clients: 0
+    }
+}
+
+/// The id other processes know `process` by: its memory slot plus one, so
+/// that 0 is never an id. A process that takes over the slot of one that
+/// exited gets the same id, so a client whose service goes away should look
+/// the service up by name again. A service forgets a client that goes away,
+/// and must be called by the new process in its slot before notifying it.
+fn id(process: &Process) -> usize {
+    process.slot + 1
+}
+
+/// Lets a process provide a service that other processes can call. A
+/// process provides one by subscribing to notifications from clients, and
+/// is found by its app name.
+///
+/// Subscribe 0: provides a service. Calls back the function at `arg2` with
+/// the client's id and the address and length of the buffer the client
+/// shared, or 0 and 0, each time a client notifies the service.
+/// Subscribe 1: calls back the function at `arg2` with the service's id each
+/// time a service notifies the process.
+/// Allow 0: returns the id of the service whose app name is the buffer.
+/// Allow `id`: shares the buffer with service `id`. See
+/// `Process::share_buffer` for what buffers can be shared.
+/// Command 0: notifies service `arg2`.
+/// Command 1: notifies client `arg2`. Only a service may notify a client,
+/// and only one that has notified it or shared a buffer with it, or the
+/// command returns EPERM.
+pub struct Ipc {
+    states: Grant<IpcState>
+}
+
+impl Ipc {
+    pub unsafe fn new() -> Ipc {
+        Ipc { states: Grant::create(new_state) }
+    }
+
+    /// The first process with IPC state for which `pred` holds.
+    fn find<P>(&self, pred: P)
+            -> Option<(&'static mut Process<'static>, IpcState)>
+            where P: Fn(&Process, &IpcState) -> bool {
+        let mut found = None;
+        self.states.each(|state| {
+            if state.process.is_null() {
+                return;
+            }
+            let process: &Process = unsafe { mem::transmute(state.process) };
+            if found.is_none() && pred(process, state) {
+                found = Some(*state);
+            }
+        });
+        found.map(|state| (unsafe { mem::transmute(state.process) }, state))
+    }
+
+    /// Stops sharing buffers of `process` with services and makes services
+    /// forget it as a client, so they cannot reach its memory or notify it
+    /// after it exits or restarts.
+    pub fn release(&self, process: &Process) {
+        let slot = process.slot;
+        self.states.each(|state| {
+            if state.process.is_null() {
+                return;
+            }
+            let service: &mut Process =
+                unsafe { mem::transmute(state.process) };
+            service.unshare_buffer(slot);
+            state.clients &= !(1 << slot);
+        });
+    }
+
+    /// Lets `service` notify `client` back.
+    fn add_client(&self, service: &mut Process, client: &Process) {
+        let slot = client.slot;
+        // The service has IPC state already, as it was found by it.
+        self.states.get(service, |state| state.clients |= 1 << slot);
+    }
+}
+
+impl Driver for Ipc {
+    fn subscribe(&mut self, process: &mut Process,
+                 subscribe_num: usize, callback: usize) -> isize {
+        if subscribe_num > 1 {
+            return ReturnCode::EINVAL.to_isize();
+        }
+        let process_ptr = process as *mut Process as *mut ();
+        let entered = self.states.enter(process, |state| {
+            state.process = process_ptr;
+            if subscribe_num == 0 {
+                state.service_callback = callback;
+            } else {
+                state.client_callback = callback;
+            }
+        });
+        match entered {
+            None => ReturnCode::ENOMEM.to_isize(),
+            Some(()) => ReturnCode::SUCCESS.to_isize()
+        }
+    }
+
+    fn command(&mut self, process: &mut Process,
+               command_num: usize, target: usize) -> isize {
+        let to_service = match command_num {
+            0 => true,
+            1 => false,
+            _ => { return ReturnCode::EINVAL.to_isize(); }
+        };
+        let found = self.find(|p, state| {
+            let callback = if to_service {
+                state.service_callback
+            } else {
+                state.client_callback
+            };
+            callback != 0 && id(p) == target
+        });
+        let (target_process, state) = match found {
+            None => { return ReturnCode::ENODEVICE.to_isize(); },
+            Some(found) => found
+        };
+
+        let callback = if to_service {
+            self.add_client(target_process, process);
+            let (addr, len) = match target_process.shared_buffer(process.slot) {
+                None => (0, 0),
+                Some(buf) => (buf.addr(), buf.len())
+            };
+            Callback {
+                pc: state.service_callback, r0: id(process), r1: addr, r2: len
+            }
+        } else {
+            let target_slot = target_process.slot;
+            // A caller that never subscribed has no IPC state, and is not
+            // given any.
+            let permitted = self.states.get(process, |caller| {
+                caller.service_callback != 0 &&
+                    caller.clients & (1 << target_slot) != 0
+            });
+            if permitted != Some(true) {
+                return ReturnCode::EPERM.to_isize();
+            }
+            Callback {
+                pc: state.client_callback, r0: id(process), r1: 0, r2: 0
+            }
+        };
+        if target_process.enqueue_callback(callback) {
+            ReturnCode::SUCCESS.to_isize()
+        } else {
+            ReturnCode::EBUSY.to_isize()
+        }
+    }
+
+    fn allow(&mut self, process: &mut Process, allow_num: usize,
+             buf: &'static mut [u8]) -> isize {
+        if allow_num == 0 {
+            let service = self.find(|p, state| {
+                state.service_callback != 0 && p.name.as_bytes() == &*buf
+            });
+            return match service {
+                None => ReturnCode::ENODEVICE.to_isize(),
+                Some((service, _)) => id(service) as isize
+            };
+        }
+
+        let service = self.find(|p, state| {
+            state.service_callback != 0 && id(p) == allow_num
+        });
+        match service {
+            None => ReturnCode::ENODEVICE.to_isize(),
+            Some((service, _)) => {
+                match service.share_buffer(process.slot, buf) {
+                    Ok(()) => {
+                        self.add_client(service, process);
+                        ReturnCode::SUCCESS.to_isize()
+                    },
+                    Err(err) => err.to_isize()
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use driver::Driver;
+    use process::Process;
+    use syscall::ReturnCode;
+    use super::{id, Ipc};
+
+    #[test]
+    fn only_services_notify_their_clients() {
+        let mut ipc = unsafe { Ipc::new() };
+        let mut service = Process::new("service", 0);
+        let mut client = Process::new("client", 1);
+        let mut stranger = Process::new("stranger", 2);
+        let success = ReturnCode::SUCCESS.to_isize();
+        assert_eq!(ipc.subscribe(&mut service, 0, 0x100), success);
+        assert_eq!(ipc.subscribe(&mut client, 1, 0x200), success);
+
+        // A process that never subscribed is refused, and gets no IPC state
+        // that would have no process in it.
+        let used = stranger.grant_region().used();
+        assert_eq!(ipc.command(&mut stranger, 1, id(&client)),
+                   ReturnCode::EPERM.to_isize());
+        assert_eq!(stranger.grant_region().used(), used);
+
+        // The service may notify the client only once the client called it.
+        assert_eq!(ipc.command(&mut service, 1, id(&client)),
+                   ReturnCode::EPERM.to_isize());
+        assert_eq!(ipc.command(&mut client, 0, id(&service)), success);
+        assert_eq!(service.callbacks.len(), 1);
+        assert_eq!(ipc.command(&mut service, 1, id(&client)), success);
+        assert_eq!(client.callbacks.len(), 1);
+
+        // Releasing processes looks through every process's IPC state.
+        ipc.release(&stranger);
+        ipc.release(&client);
+        assert_eq!(ipc.command(&mut service, 1, id(&client)),
+                   ReturnCode::EPERM.to_isize());
+    }
+}
@@ -13,102 +13,486 @@ extern crate support;
 
 use core::prelude::*;
 use core::intrinsics;
+use core::mem;
 
+use app_header::{AppHeader, HeaderError};
 use array_list::ArrayList;
-use process::Process;
+use mpu_region::RegionLayout;
+use platform::cortex::mpu::MPU;
+use platform::cortex::scb::SCB;
+use platform::cortex::systick::SysTick;
+use process::{Callback, Process};
+use scheduler::Scheduler;
+use slot_allocator::NUM_SLOTS;
 
 mod std {
     pub use core::*;
 }
 
+mod app_header;
 mod array_list;
+mod clock;
 pub mod config;
+mod deferred_call;
+mod driver;
+mod fault;
+mod grant;
+mod grant_region;
+mod ipc;
+mod mpu_region;
 mod ring_buffer;
 mod process;
+mod registry;
+mod scheduler;
+mod shell;
+mod slot_allocator;
+mod stats;
 mod syscall;
 mod util;
 
+/// Bounds of the flash region apps are programmed into, from the linker
+/// script.
 #[allow(improper_ctypes)]
 extern {
-    static _sapps: fn();
-    static _eapps: fn();
+    static _sapps: u8;
+    static _eapps: u8;
 }
 
-unsafe fn load_apps(proc_arr: &mut ArrayList<Process>) {
+/// Every process the kernel has created, for the kernel shell. Set once the
+/// apps are loaded.
+static mut PROCESSES: Option<ArrayList<Process<'static>>> = None;
+/// Storage for `PROCESSES`, which has room for a process in every memory
+/// slot. Words rather than bytes, so the processes in it are aligned. `main`
+/// checks that it is big enough.
+static mut PROCESS_BUF: [usize; 768] = [0; 768];
+
+/// Calls `f` with the header of each app in the apps flash region and
+/// whether the app can run, until `f` returns `false`. The apps sit back to
+/// back, each starting with its header, and end at the first word that is
+/// not `APP_MAGIC` (erased flash, or the terminator `make apps` writes).
+unsafe fn each_app<F>(mut f: F)
+        where F: FnMut(&'static AppHeader, Result<(), HeaderError>) -> bool {
+    let start = &_sapps as *const u8 as usize;
+    let end = &_eapps as *const u8 as usize;
+
+    let mut addr = start;
+    while addr + mem::size_of::<AppHeader>() <= end {
+        let header: &'static AppHeader = mem::transmute(addr);
+        if header.magic != app_header::APP_MAGIC {
+            break;
+        }
+
+        let next = header.next(end);
+        let checked = header.validate(end, process::APP_MEMORY_SIZE,
+                                      mem::size_of::<Option<Callback>>(),
+                                      process::SUBREGION_SIZE)
+            .and_then(|()| {
+                // The process must not see its neighbours' code.
+                match RegionLayout::covering_only(addr,
+                                                  header.total_size as usize) {
+                    None => Err(HeaderError::Unaligned),
+                    Some(_) => Ok(())
+                }
+            });
+        if !f(header, checked) {
+            break;
+        }
+        match next {
+            // Without a size we cannot tell where the next app is.
+            None => { break; },
+            Some(next) => { addr = next; }
+        }
+    }
+}
+
+/// Says on the console that the app `header` starts cannot run.
+fn print_refusal(header: &AppHeader, err: HeaderError) {
+    util::print("Refusing app ");
+    match header.name() {
+        Some(name) => util::print(name),
+        None => {
+            util::print("at ");
+            util::print_hex(header.start());
+        }
+    }
+    util::print(": ");
+    util::println(err.description());
+}
 
-    let (start_ptr, end_ptr) = (&_sapps as *const fn(), &_eapps as *const fn());
+/// Creates a process for each app in the apps flash region that can run.
+unsafe fn load_apps(proc_arr: &mut ArrayList<Process>) {
+    each_app(|header, checked| {
+        match checked {
+            Err(err) => {
+                print_refusal(header, err);
+                true
+            },
+            Ok(()) => start_app(proc_arr, header)
+        }
+    });
+}
 
-    let mut ptr = start_ptr;
-    while ptr < end_ptr {
-        match process::Process::create(*ptr) {
-            Err(_) => { break; },
-            Ok(process) => {
-                if !proc_arr.add(process) {
-                    break;
+/// Creates a process for `app`, which must be able to run, and makes it
+/// ready to run. Returns `false` if there is no memory slot left for it.
+fn start_app(proc_arr: &mut ArrayList<Process>, app: &'static AppHeader)
+        -> bool {
+    match process::Process::create(app) {
+        Err(_) => false,
+        Ok(process) => {
+            match add_process(proc_arr, process) {
+                None => false,
+                // The process's init function is already in its callback
+                // queue, so it is ready to run.
+                Some(process) => {
+                    process.wake();
+                    true
                 }
             }
         }
-        ptr = ptr.offset(1);
     }
 }
 
-#[no_mangle]
-pub extern fn main() {
-    let mut proc_list = unsafe {
-        config::config();
+/// Adds a new process to the process list, taking over the entry of a
+/// process that exited if there is one.
+fn add_process<'a, 'b>(proc_arr: &'b mut ArrayList<Process<'a>>,
+                       process: Process<'a>) -> Option<&'b mut Process<'a>> {
+    fn exited(process: &Process) -> bool {
+        process.state == process::State::Exited
+    }
+    proc_arr.add_or_replace(process, exited)
+}
 
-        let mut buf : [u8; 1024] = [0; 1024];
-        let mut list = ArrayList::new(8, intrinsics::transmute(&mut buf));
-        load_apps(&mut list);
-        list
-    };
+/// Runs the work interrupt handlers have left pending.
+unsafe fn run_deferred_calls() {
+    loop {
+        match support::atomic(|| deferred_call::PENDING.take()) {
+            None => { break; },
+            Some(call) => config::deferred_call(call)
+        }
+    }
+}
 
-    let subscribe_drivers = unsafe { &syscall::SUBSCRIBE_DRIVERS };
-    let cmd_drivers = unsafe { &syscall::CMD_DRIVERS };
+/// Takes the next runnable process off the ready queue. If there is none
+/// and no deferred call is pending, sleeps until an interrupt arrives and
+/// returns `None`.
+unsafe fn next_ready<'a>() -> Option<&'a mut Process<'a>> {
+    // Interrupts stay masked between checking for work and sleeping so a
+    // deferred call cannot slip in unnoticed. WFI still returns on a pending
+    // interrupt while they are masked, and it is taken once we unmask.
+    let next = support::atomic(|| {
+        let next = scheduler::READY.next();
+        if next.is_none() && !deferred_call::PENDING.has_pending() {
+            support::wfi();
+        }
+        next
+    });
+    next.map(|ptr| mem::transmute(ptr))
+}
 
-    // Circular iterator is temporary. We actually want a run queue so we can
-    // sleep when there is no work to be done.
-    let mut iter = proc_list.circular_iterator();
-    let mut process = iter.next().unwrap();
+/// Runs `process` until it waits with no callbacks left to handle or uses up
+/// its timeslice.
+unsafe fn run_process(process: &mut Process, systick: &mut SysTick) {
+    systick.reset();
+    systick.enable(true);
     loop {
+        // The slice may also run out while the kernel is handling a system
+        // call, in which case the SysTick handler leaves the kernel alone.
+        if systick.overflowed() {
+            preempt(process);
+            break;
+        }
+
         match process.state {
             process::State::Running => {
-                unsafe { process.switch_to(); }
+                process.switch_to();
             },
             process::State::Waiting => {
-                unsafe {
-                    match process.callbacks.dequeue() {
-                        None => {
-                            process = iter.next().unwrap();
-                            continue;
-                        },
-                        Some(cb) => {
-                            process.state = process::State::Running;
-                            process.switch_to_callback(cb);
-                        }
+                match process.callbacks.dequeue() {
+                    None => { break; },
+                    Some(cb) => {
+                        process.state = process::State::Running;
+                        process.switch_to_callback(cb);
                     }
-                };
-            }
+                }
+            },
+            process::State::Faulted | process::State::Exited |
+                    process::State::Suspended => { break; }
+        }
+
+        if process.state == process::State::Faulted ||
+                syscall::SWITCH_REASON == syscall::SWITCH_FAULT {
+            fault::process_fault(process);
+            config::release_subscriptions(process as *mut Process as *mut ());
+            restart(process);
+            break;
+        }
+
+        if syscall::SWITCH_REASON == syscall::SWITCH_TIMESLICE {
+            preempt(process);
+            break;
         }
+
         let process_ptr = process as *mut Process as *mut ();
-        match process.svc_number() {
+        let svc_number = process.svc_number();
+        match svc_number {
+            Some(num) => process.stats.syscall(num as usize),
+            None => {}
+        }
+        match svc_number {
             Some(syscall::WAIT) => {
                 process.state = process::State::Waiting;
                 process.pop_syscall_stack();
-                process = iter.next().unwrap();
+                if process.callbacks.len() > 0 {
+                    // Go to the back of the line rather than starving
+                    // everyone else.
+                    process.wake();
+                }
+                break;
+            },
+            Some(syscall::EXIT) => {
+                config::release_subscriptions(process_ptr);
+                if !restart(process) {
+                    process.exit();
+                }
+                break;
+            },
+            Some(syscall::SUBSCRIBE) | Some(syscall::COMMAND) |
+                    Some(syscall::ALLOW) if !permitted(process) => {
+                process.set_r0(syscall::ReturnCode::EPERM.to_isize());
             },
             Some(syscall::SUBSCRIBE) => {
-                let driver = subscribe_drivers[process.r0()];
-                let res = driver(process_ptr, process.r1(),process.r2());
+                let (r0, r1, r2) = (process.r0(), process.r1(), process.r2());
+                let res = syscall::subscribe(process, r0, r1, r2);
                 process.set_r0(res);
             },
             Some(syscall::COMMAND) => {
-                let driver = cmd_drivers[process.r0()];
-                let res = driver(process_ptr, process.r1(), process.r2());
+                let (r0, r1, r2) = (process.r0(), process.r1(), process.r2());
+                let res = syscall::command(process, r0, r1, r2);
                 process.set_r0(res);
             },
-            _ => {}
+            Some(syscall::ALLOW) => {
+                let (r0, r1, r2, r3) =
+                    (process.r0(), process.r1(), process.r2(), process.r3());
+                let res = syscall::allow(process, r0, r1, r2, r3);
+                process.set_r0(res);
+            },
+            Some(syscall::MEMOP) => {
+                let (r0, r1) = (process.r0(), process.r1());
+                let res = syscall::memop(process, r0, r1);
+                process.set_r0(res);
+            },
+            _ => {
+                process.set_r0(syscall::ReturnCode::ENOSUPPORT.to_isize());
+            }
         }
     }
+    systick.disable();
 }
 
+/// Whether `process` declared, in its app header, the driver it passes to
+/// the system call it is making. Logs the calls it is refused.
+fn permitted(process: &Process) -> bool {
+    let driver_num = process.r0();
+    if process.app.permits(driver_num) {
+        return true;
+    }
+    util::print("Process ");
+    util::print(process.name);
+    util::print(" (slot ");
+    util::print_dec(process.slot as u32);
+    util::print(") may not use driver ");
+    util::print_dec(driver_num as u32);
+    util::println("");
+    false
+}
+
+/// Runs a process that stopped again from the beginning, if its restart
+/// policy allows it. Returns whether the process will run again.
+unsafe fn restart(process: &mut Process) -> bool {
+    match process.restart() {
+        None => false,
+        Some(delay) => {
+            util::print("Restarting ");
+            util::print(process.name);
+            util::print(" after ");
+            util::print_dec(delay);
+            util::println(" ticks");
+
+            // Without room for an alarm, or if the delay is too short for
+            // one, the process restarts right away.
+            let init_fn = process.init_fn;
+            if delay == 0 ||
+                    config::set_timer_alarm(process, delay, init_fn,
+                                            false).is_none() {
+                let init = process.init_callback();
+                process.enqueue_callback(init);
+            }
+            true
+        }
+    }
+}
+
+/// Runs a command typed into the kernel shell.
+fn shell_command(command: shell::Command) {
+    use shell::Command;
+
+    match command {
+        Command::Help => {
+            for line in shell::HELP.iter() {
+                util::println(*line);
+            }
+        },
+        Command::Processes => print_processes(),
+        Command::Stats(slot) => with_process(slot, |process| {
+            for stat in range(0, stats::NUM_STATS) {
+                util::print("  ");
+                util::print(stats::STAT_NAMES[stat]);
+                util::print(": ");
+                util::print_dec(process.stats.get(stat).unwrap_or(0));
+                util::println("");
+            }
+        }),
+        Command::Stop(slot) => with_process(slot, |process| {
+            if !process.suspend() {
+                util::println("Process is not running");
+            }
+        }),
+        Command::Start(slot) => with_process(slot, |process| {
+            if !process.resume() {
+                util::println("Process is not suspended");
+            }
+        }),
+        Command::Restart(slot) => with_process(slot, |process| {
+            config::release_subscriptions(process as *mut Process as *mut ());
+            process.reset();
+            let init = process.init_callback();
+            process.enqueue_callback(init);
+        }),
+        Command::Load(name) => load_app(name),
+        Command::Drivers => config::print_drivers(),
+        Command::ChipInfo => config::chip_info(),
+        Command::Uptime => {
+            let millis = config::clock_millis();
+            util::print_dec((millis / 1000) as u32);
+            util::print(" s ");
+            util::print_dec((millis % 1000) as u32);
+            util::println(" ms");
+        }
+    }
+}
+
+/// Calls `f` with the process in memory slot `slot`, or says there is none.
+fn with_process<F: FnOnce(&mut Process)>(slot: usize, f: F) {
+    let procs = unsafe { PROCESSES.as_mut().expect("PROCESSES is None!") };
+    for i in range(0, procs.len()) {
+        // An exited process has given its slot up.
+        if procs[i].slot == slot && procs[i].state != process::State::Exited {
+            f(&mut procs[i]);
+            return;
+        }
+    }
+    util::println("No process in that slot");
+}
+
+/// Starts a process for the app named `name`, unless one that has not
+/// exited has it already. A process that faulted keeps its memory slot, and
+/// is run again with `restart`.
+fn load_app(name: &str) {
+    let procs = unsafe { PROCESSES.as_mut().expect("PROCESSES is None!") };
+    for i in range(0, procs.len()) {
+        if procs[i].name == name &&
+                procs[i].state != process::State::Exited {
+            util::print("App already has a process in slot ");
+            util::print_dec(procs[i].slot as u32);
+            util::println("");
+            return;
+        }
+    }
+
+    let mut found = false;
+    unsafe {
+        each_app(|header, checked| {
+            if header.name() != Some(name) {
+                return true;
+            }
+            found = true;
+            match checked {
+                Err(err) => print_refusal(header, err),
+                Ok(()) => if !start_app(procs, header) {
+                    util::println("No free memory slot");
+                }
+            }
+            false
+        });
+    }
+    if !found {
+        util::println("No app with that name");
+    }
+}
+
+/// Prints each process's memory slot, name, state and how many callbacks it
+/// has queued and has had dropped.
+fn print_processes() {
+    let procs = unsafe { PROCESSES.as_ref().expect("PROCESSES is None!") };
+    for i in range(0, procs.len()) {
+        let process = &procs[i];
+        if process.state == process::State::Exited {
+            continue;
+        }
+        util::print_dec(process.slot as u32);
+        util::print(": ");
+        util::print(process.name);
+        util::print(" ");
+        util::print(process.state.description());
+        util::print(", ");
+        util::print_dec(process.callbacks.len() as u32);
+        util::print(" callbacks queued, ");
+        util::print_dec(process.stats.callbacks_dropped);
+        util::println(" dropped");
+    }
+}
+
+/// Sends a process that is still running to the back of the ready queue.
+fn preempt(process: &mut Process) {
+    process.stats.timeslice();
+    process.wake();
+}
+
+#[no_mangle]
+pub extern fn main() {
+    unsafe {
+        // Faults in processes are handled by the kernel rather than
+        // escalating to a HardFault.
+        SCB::get().enable_fault_handlers();
+        // Processes only get the regions set up for them on each switch. The
+        // kernel keeps the default memory map.
+        MPU::get().enable(false, true);
+
+        config::config();
+        support::PANIC_HOOK = Some(fault::panic_hook);
+
+        if mem::size_of::<Process>() * NUM_SLOTS >
+                PROCESS_BUF.len() * mem::size_of::<usize>() {
+            panic!("PROCESS_BUF cannot hold a process for every slot");
+        }
+        let mut list = ArrayList::new(NUM_SLOTS,
+                                      intrinsics::transmute(&mut PROCESS_BUF));
+        load_apps(&mut list);
+        PROCESSES = Some(list);
+        config::SHELL_HOOK = Some(shell_command);
+    }
+
+    let systick = unsafe { SysTick::get() };
+    systick.set_timer(config::TIMESLICE_TICKS);
+
+    loop {
+        unsafe {
+            // Drivers finish handling interrupts before any process runs.
+            run_deferred_calls();
+            match next_ready() {
+                Some(process) => run_process(process, systick),
+                None => {}
+            }
+        }
+    }
+}
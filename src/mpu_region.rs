@@ -0,0 +1,199 @@
+use core::cmp;
+use core::prelude::*;
+
+/// Smallest MPU region, 32 bytes
+pub const MIN_SIZE_LOG2: usize = 5;
+
+/// Regions smaller than 256 bytes cannot disable subregions.
+const MIN_SUBREGIONS_LOG2: usize = 8;
+
+/// Where an MPU region lies: a block of memory whose size is a power of two
+/// and whose base is a multiple of its size. Any of the block's eight equal
+/// subregions may be left out.
+#[derive(Copy,PartialEq,Eq,Debug)]
+pub struct RegionLayout {
+    pub base: usize,
+    pub size_log2: usize,
+    /// Bit `n` set leaves out subregion `n`.
+    pub disabled_subregions: u8
+}
+
+impl RegionLayout {
+    /// The smallest region that covers `len` bytes at `start`, leaving out
+    /// the subregions that do not overlap them. Memory outside the range is
+    /// only covered where it shares a subregion with the range, so ranges
+    /// that are themselves aligned blocks are covered exactly.
+    pub fn covering(start: usize, len: usize) -> Option<RegionLayout> {
+        if len == 0 || len > !0 - start {
+            return None;
+        }
+        let end = start + len;
+        for size_log2 in range(MIN_SIZE_LOG2, 32) {
+            let size = 1 << size_log2;
+            let base = start & !(size - 1);
+            if end - base > size {
+                continue;
+            }
+
+            let mut disabled = 0;
+            if size_log2 >= MIN_SUBREGIONS_LOG2 {
+                let sub = size / 8;
+                for i in range(0, 8) {
+                    let sub_start = base + i * sub;
+                    if sub_start + sub <= start || sub_start >= end {
+                        disabled |= 1 << i;
+                    }
+                }
+            }
+            return Some(RegionLayout {
+                base: base,
+                size_log2: size_log2,
+                disabled_subregions: disabled
+            });
+        }
+        None
+    }
+
+    /// The region covering exactly `len` bytes at `start` and nothing else,
+    /// if they form a block whose size is a power of two of at least 32 bytes
+    /// and whose start is a multiple of its size.
+    pub fn exactly(start: usize, len: usize) -> Option<RegionLayout> {
+        match RegionLayout::covering(start, len) {
+            Some(region) if region.base == start &&
+                    1 << region.size_log2 == len &&
+                    region.disabled_subregions == 0 => Some(region),
+            _ => None
+        }
+    }
+
+    /// The region `covering` `len` bytes at `start`, if it covers no other
+    /// memory.
+    pub fn covering_only(start: usize, len: usize) -> Option<RegionLayout> {
+        match RegionLayout::covering(start, len) {
+            Some(region) if region.bounds() == (start, start + len) =>
+                Some(region),
+            _ => None
+        }
+    }
+
+    /// Where the memory the region covers starts and ends, leaving out the
+    /// disabled subregions, which are only ever at either end.
+    pub fn bounds(&self) -> (usize, usize) {
+        let size = 1 << self.size_log2;
+        let sub = size / 8;
+        let mut start = self.base + size;
+        let mut end = self.base;
+        for i in range(0, 8) {
+            if self.disabled_subregions & (1 << i) == 0 {
+                start = cmp::min(start, self.base + i * sub);
+                end = cmp::max(end, self.base + (i + 1) * sub);
+            }
+        }
+        (start, end)
+    }
+
+    /// The SRD and SIZE fields of the region's attribute and size register.
+    pub fn att_size(&self) -> usize {
+        ((self.disabled_subregions as usize) << 8) |
+            ((self.size_log2 - 1) << 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use super::RegionLayout;
+
+    #[test]
+    fn covers_aligned_block_exactly() {
+        let region = RegionLayout::covering(0x20002000, 2048).unwrap();
+        assert_eq!(region, RegionLayout {
+            base: 0x20002000,
+            size_log2: 11,
+            disabled_subregions: 0
+        });
+        assert_eq!(region.att_size(), 10 << 1);
+    }
+
+    #[test]
+    fn leaves_out_unused_subregions() {
+        // The top three quarters of a 2K block
+        let region = RegionLayout::covering(0x20002200, 0x600).unwrap();
+        assert_eq!(region.base, 0x20002000);
+        assert_eq!(region.size_log2, 11);
+        assert_eq!(region.disabled_subregions, 0b00000011);
+        assert_eq!(region.att_size(), (0b11 << 8) | (10 << 1));
+    }
+
+    #[test]
+    fn keeps_partly_used_subregions() {
+        let region = RegionLayout::covering(0x40010, 0x3e0).unwrap();
+        assert_eq!(region.base, 0x40000);
+        assert_eq!(region.size_log2, 10);
+        assert_eq!(region.disabled_subregions, 0);
+    }
+
+    #[test]
+    fn grows_to_cover_unaligned_range() {
+        // Crosses a 4K boundary, so needs an 8K block
+        let region = RegionLayout::covering(0x40f00, 0x200).unwrap();
+        assert_eq!(region.base, 0x40000);
+        assert_eq!(region.size_log2, 13);
+        assert_eq!(region.disabled_subregions, 0b11100111);
+    }
+
+    #[test]
+    fn small_regions_have_no_subregions() {
+        let region = RegionLayout::covering(0x1004, 8).unwrap();
+        assert_eq!(region, RegionLayout {
+            base: 0x1000,
+            size_log2: 5,
+            disabled_subregions: 0
+        });
+    }
+
+    #[test]
+    fn exactly_takes_aligned_blocks_only() {
+        assert_eq!(RegionLayout::exactly(0x20001000, 256), Some(RegionLayout {
+            base: 0x20001000,
+            size_log2: 8,
+            disabled_subregions: 0
+        }));
+        assert_eq!(RegionLayout::exactly(0x20001080, 256), None);
+        assert_eq!(RegionLayout::exactly(0x20001000, 200), None);
+        assert_eq!(RegionLayout::exactly(0x20001000, 16), None);
+    }
+
+    #[test]
+    fn covering_only_refuses_shared_subregions() {
+        // An app packed right after a 0x300 byte one shares a subregion with
+        // it.
+        assert_eq!(RegionLayout::covering_only(0x40000, 0x300),
+                   RegionLayout::covering(0x40000, 0x300));
+        assert_eq!(RegionLayout::covering_only(0x40300, 0x120), None);
+        assert_eq!(RegionLayout::covering_only(0x40010, 0x3e0), None);
+        assert_eq!(RegionLayout::covering_only(0x1004, 8), None);
+
+        let region = RegionLayout::covering_only(0x20002200, 0x600).unwrap();
+        assert_eq!(region.bounds(), (0x20002200, 0x20002800));
+    }
+
+    #[test]
+    fn process_memory_leaves_out_callbacks_and_grants() {
+        // A 2K process slot whose bottom subregion holds the callback ring
+        // and whose top one holds the grant region.
+        let region = RegionLayout::covering_only(0x20002100, 0x600).unwrap();
+        assert_eq!(region.base, 0x20002000);
+        assert_eq!(region.disabled_subregions, 0b10000001);
+
+        // A ring that ends partway into the subregion would be covered.
+        assert_eq!(RegionLayout::covering_only(0x200020dc, 0x624), None);
+        let region = RegionLayout::covering(0x200020dc, 0x624).unwrap();
+        assert_eq!(region.disabled_subregions, 0b10000000);
+    }
+
+    #[test]
+    fn refuses_empty_range() {
+        assert_eq!(RegionLayout::covering(0x1000, 0), None);
+    }
+}
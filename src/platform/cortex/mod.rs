@@ -1 +1,3 @@
 pub mod mpu;
+pub mod scb;
+pub mod systick;
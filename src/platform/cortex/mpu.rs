@@ -1,7 +1,22 @@
-use core::intrinsics::{volatile_load, volatile_store};
+use core::intrinsics::{self, volatile_load, volatile_store};
 
 pub const BASE_ADDRESS : usize = 0xE000ED90;
 
+// Attribute bits for `Region::set_att_size`
+
+/// Disable instruction fetches
+pub const XN: usize = 1 << 28;
+/// Read/write at any privilege level
+pub const AP_FULL_ACCESS: usize = 0b011 << 24;
+/// Read only at any privilege level
+pub const AP_READ_ONLY: usize = 0b110 << 24;
+/// Normal, write-through memory (TEX=000, C=1, B=0), as recommended for
+/// flash
+pub const FLASH_MEMORY: usize = 1 << 17;
+/// Normal, shareable, write-back memory (TEX=000, S=1, C=1, B=1), as
+/// recommended for SRAM
+pub const SRAM_MEMORY: usize = (1 << 18) | (1 << 17) | (1 << 16);
+
 /// Memory Protection Unit
 #[allow(dead_code,missing_copy_implementations)]
 pub struct MPU {
@@ -13,6 +28,13 @@ pub struct MPU {
 }
 
 impl MPU {
+    /// Returns the MPU
+    ///
+    /// _Not re-entrant_: callers must make sure there is only one user.
+    pub unsafe fn get() -> &'static mut MPU {
+        intrinsics::transmute(BASE_ADDRESS)
+    }
+
     /// Enables the MPU
     ///
     /// # Arguments
@@ -55,6 +77,13 @@ pub struct Region {
 }
 
 impl Region {
+    /// Returns the MPU region `region_num`
+    ///
+    /// _Not re-entrant_
+    pub unsafe fn get(region_num: RegionNum) -> Region {
+        Region { mpu: MPU::get(), region_num: region_num }
+    }
+
     /// Enables the Region
     ///
     /// _Non re-entrant_
@@ -0,0 +1,67 @@
+use core::intrinsics::{self, volatile_load, volatile_store};
+
+/// Address of SHCSR, the first of the fault related System Control Block
+/// registers.
+pub const BASE_ADDRESS : usize = 0xE000ED24;
+
+/// The fault handling registers of the System Control Block
+#[repr(C, packed)]
+#[allow(dead_code,missing_copy_implementations)]
+pub struct SCB {
+    shcsr: usize,
+    cfsr: usize,
+    hfsr: usize,
+    dfsr: usize,
+    mmfar: usize,
+    bfar: usize,
+    afsr: usize,
+}
+
+impl SCB {
+    /// Returns the System Control Block
+    pub unsafe fn get() -> &'static mut SCB {
+        intrinsics::transmute(BASE_ADDRESS)
+    }
+
+    /// Enables the MemManage, BusFault and UsageFault exceptions. Otherwise
+    /// all of these faults escalate to HardFault.
+    pub fn enable_fault_handlers(&mut self) {
+        unsafe {
+            let val = volatile_load(&self.shcsr) | (0b111 << 16);
+            volatile_store(&mut self.shcsr, val);
+        }
+    }
+
+    /// Configurable Fault Status Register: MemManage (bits 7:0), BusFault
+    /// (bits 15:8) and UsageFault (bits 31:16) status.
+    pub fn cfsr(&self) -> usize {
+        unsafe { volatile_load(&self.cfsr) }
+    }
+
+    /// HardFault Status Register
+    pub fn hfsr(&self) -> usize {
+        unsafe { volatile_load(&self.hfsr) }
+    }
+
+    /// MemManage Fault Address Register. Only valid when `CFSR.MMARVALID` is
+    /// set.
+    pub fn mmfar(&self) -> usize {
+        unsafe { volatile_load(&self.mmfar) }
+    }
+
+    /// BusFault Address Register. Only valid when `CFSR.BFARVALID` is set.
+    pub fn bfar(&self) -> usize {
+        unsafe { volatile_load(&self.bfar) }
+    }
+
+    /// Clears all fault status bits so the next fault is reported on its own.
+    pub fn clear_faults(&mut self) {
+        unsafe {
+            // Both status registers are write-one-to-clear
+            let cfsr = volatile_load(&self.cfsr);
+            volatile_store(&mut self.cfsr, cfsr);
+            let hfsr = volatile_load(&self.hfsr);
+            volatile_store(&mut self.hfsr, hfsr);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use core::intrinsics::{self, volatile_load, volatile_store};
+
+pub const BASE_ADDRESS : usize = 0xE000E010;
+
+/// Largest value the 24-bit reload register can hold
+pub const MAX_TICKS : usize = 0x00FFFFFF;
+
+/// The Cortex-M system timer (SysTick)
+#[repr(C, packed)]
+#[allow(dead_code,missing_copy_implementations)]
+pub struct SysTick {
+    control: usize,
+    reload: usize,
+    current: usize,
+    calibration: usize,
+}
+
+impl SysTick {
+    /// Returns the SysTick peripheral
+    ///
+    /// _Not re-entrant_: callers must make sure there is only one user.
+    pub unsafe fn get() -> &'static mut SysTick {
+        intrinsics::transmute(BASE_ADDRESS)
+    }
+
+    /// Sets the number of processor clock ticks the timer counts down from.
+    /// Values above `MAX_TICKS` are truncated.
+    pub fn set_timer(&mut self, ticks: usize) {
+        unsafe {
+            volatile_store(&mut self.reload, ticks & MAX_TICKS);
+        }
+    }
+
+    /// Restarts the count down from the reload value and clears the
+    /// overflow flag.
+    pub fn reset(&mut self) {
+        unsafe {
+            volatile_store(&mut self.current, 0);
+        }
+    }
+
+    /// Starts the timer, clocked from the processor clock.
+    ///
+    /// # Arguments
+    ///
+    /// * `interrupt` - Raise the SysTick exception when the timer reaches
+    /// zero.
+    pub fn enable(&mut self, interrupt: bool) {
+        let val = 1 | ((interrupt as usize) << 1) | (1 << 2);
+        unsafe {
+            volatile_store(&mut self.control, val);
+        }
+    }
+
+    /// Stops the timer
+    pub fn disable(&mut self) {
+        unsafe {
+            volatile_store(&mut self.control, 0);
+        }
+    }
+
+    /// Whether the timer reached zero since this was last called (or since
+    /// the last `reset`). Reading the flag clears it.
+    pub fn overflowed(&self) -> bool {
+        unsafe {
+            volatile_load(&self.control) & (1 << 16) != 0
+        }
+    }
+
+    /// Ticks left before the timer reaches zero
+    pub fn value(&self) -> usize {
+        unsafe {
+            volatile_load(&self.current) & MAX_TICKS
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use core::prelude::*;
 use core::intrinsics;
 use super::nvic;
-use hil::timer::Timer;
+use hil::timer::{OverflowTimer, Timer};
 
 #[repr(C, packed)]
 #[allow(missing_copy_implementations)]
@@ -36,14 +36,22 @@ pub const AST_BASE: isize = 0x400F0800;
 #[allow(missing_copy_implementations)]
 pub struct Ast {
     addr: *mut AstRegisters,
-    callback: fn()
+    callback: fn(),
+    overflow_callback: fn()
 }
 
 pub static mut Ast0: Ast =
-    Ast {addr: AST_BASE as *mut AstRegisters, callback: noop };
+    Ast {addr: AST_BASE as *mut AstRegisters, callback: noop,
+         overflow_callback: noop };
 
 fn noop() {}
 
+/// Nominal frequency of each clock the counter can run from, in Hz, by
+/// `Clock` number. The APB clock is the 48MHz core clock set up by the
+/// bootloader, undivided. Generic clock 2 is not set up by the kernel, so its
+/// frequency is not known.
+const CLOCK_FREQUENCIES: [u32; 5] = [115200, 32768, 48000000, 0, 1024];
+
 #[repr(uint)]
 pub enum Clock {
     ClockRCSys = 0,
@@ -56,15 +64,25 @@ pub enum Clock {
 impl Copy for Clock {}
 
 impl Ast {
-    pub unsafe fn new(callback: fn()) -> Ast {
+    /// `callback` is called when the alarm goes off, and `overflow_callback`
+    /// each time the counter wraps around once the overflow interrupt is
+    /// enabled.
+    pub unsafe fn new(callback: fn(), overflow_callback: fn()) -> Ast {
         Ast0.callback = callback;
-        Ast {addr: Ast0.addr, callback: Ast0.callback}
+        Ast0.overflow_callback = overflow_callback;
+        Ast {addr: Ast0.addr, callback: Ast0.callback,
+             overflow_callback: Ast0.overflow_callback}
     }
 
+    /// Starts the counter, which then runs freely: alarms are set and
+    /// disabled without stopping it.
     pub fn setup(&mut self) {
         self.select_clock(Clock::ClockRCSys);
         self.set_prescalar(0);
         self.clear_alarm();
+        self.clear_overflow();
+        self.set_counter(0);
+        self.enable();
     }
 
     pub fn clock_busy(&self) -> bool {
@@ -89,6 +107,15 @@ impl Ast {
         }
     }
 
+    // Clears the overflow bit in the status register (indicating the counter
+    // has wrapped around to 0).
+    pub fn clear_overflow(&mut self) {
+        while self.busy() {}
+        unsafe {
+            intrinsics::volatile_store(&mut (*self.addr).scr, 1);
+        }
+    }
+
     // Clears the per0 bit in the status register (indicating the alarm value
     // has been reached).
     pub fn clear_periodic(&mut self) {
@@ -209,26 +236,62 @@ impl Timer for Ast {
         }
     }
 
+    /// The frequency of the selected clock, divided by 2^(PSEL + 1) by the
+    /// prescaler. 0 if the clock's frequency is not known.
+    fn frequency(&self) -> u32 {
+        let (clock, cr) = unsafe {
+            (intrinsics::volatile_load(&(*self.addr).clock),
+             intrinsics::volatile_load(&(*self.addr).cr))
+        };
+        let source = match CLOCK_FREQUENCIES.get(((clock >> 8) & 0x7) as usize) {
+            Some(&frequency) => frequency,
+            None => 0
+        };
+        let shift = ((cr >> 16) & 0x1f) + 1;
+        if shift >= 32 { 0 } else { source >> shift as usize }
+    }
+
     fn disable_alarm(&mut self) {
-        self.disable();
+        self.disable_alarm_irq();
         self.clear_alarm();
     }
 
     fn set_alarm(&mut self, tics: u32) {
-        self.disable();
         self.clear_alarm();
-        self.enable_alarm_irq();
         while self.busy() {}
         unsafe {
             intrinsics::volatile_store(&mut (*self.addr).ar0, tics);
         }
-        self.enable();
+        self.enable_alarm_irq();
+    }
+}
+
+impl OverflowTimer for Ast {
+    fn overflow_pending(&self) -> bool {
+        unsafe {
+            intrinsics::volatile_load(&(*self.addr).sr) & 1 != 0
+        }
     }
 }
 
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern fn AST_ALARM_Handler() {
-    unsafe { let f = Ast0.callback; f() }
+    unsafe {
+        // Otherwise the interrupt stays asserted until the callback's work
+        // gets done.
+        Ast0.clear_alarm();
+        let f = Ast0.callback;
+        f()
+    }
 }
 
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern fn AST_OVF_Handler() {
+    unsafe {
+        Ast0.clear_overflow();
+        let f = Ast0.overflow_callback;
+        f()
+    }
+}
@@ -1,24 +1,138 @@
-use core::intrinsics::{atomic_xadd, volatile_load, volatile_store};
+use core::cmp;
+use core::intrinsics::{volatile_load, volatile_store};
 use core::mem;
 use core::prelude::*;
 use core::raw;
+use core::u32;
 
+use app_header::{self, AppHeader};
+use grant_region::GrantRegion;
+use mpu_region::RegionLayout;
+use platform::cortex::mpu::{self, Region, RegionNum};
 use ring_buffer::RingBuffer;
-use syscall;
+use scheduler::{self, Scheduler};
+use slot_allocator::{SlotAllocator, NUM_SLOTS};
+use stats::Stats;
+use syscall::{self, ReturnCode};
 
-/// Size of each processes's memory region in bytes
-pub const PROC_MEMORY_SIZE : usize = 2048;
+/// Size of each processes's memory region in bytes. A power of two of at
+/// least 256 bytes, so each region can be covered by one MPU region.
+pub const PROC_MEMORY_SIZE : usize = 1 << PROC_MEMORY_SIZE_LOG2;
+const PROC_MEMORY_SIZE_LOG2 : usize = 11;
 
-static mut MEMORIES: [[u8; PROC_MEMORY_SIZE]; 8] = [[0; PROC_MEMORY_SIZE]; 8];
-static mut FREE_MEMORY_IDX: usize = 0;
+/// Size of each of the eight subregions of the MPU region covering a
+/// process's memory, which the MPU can leave out of the region one by one.
+pub const SUBREGION_SIZE : usize = PROC_MEMORY_SIZE / 8;
+
+/// Bytes at the top of each process's memory kept for the state drivers
+/// allocate through grants. A single MPU subregion, so the MPU region the
+/// process gets can leave it out.
+pub const GRANT_MEMORY_SIZE : usize = SUBREGION_SIZE;
+
+/// Bytes of each process's memory below its grant region.
+pub const APP_MEMORY_SIZE : usize = PROC_MEMORY_SIZE - GRANT_MEMORY_SIZE;
+
+/// Placed by the linker script on a `PROC_MEMORY_SIZE` boundary, which the
+/// MPU requires of a region that size.
+#[link_section = ".app_memory"]
+static mut MEMORIES: [[u8; PROC_MEMORY_SIZE]; NUM_SLOTS] =
+    [[0; PROC_MEMORY_SIZE]; NUM_SLOTS];
+static mut SLOTS: SlotAllocator = SlotAllocator { used: [false; NUM_SLOTS] };
+
+/// The grant region of the process in memory slot `slot`, if the slot is in
+/// use.
+pub fn grant_region(slot: usize) -> Option<GrantRegion<'static>> {
+    unsafe {
+        if slot >= NUM_SLOTS || !SLOTS.is_used(slot) {
+            return None;
+        }
+        Some(GrantRegion::new(&mut MEMORIES[slot][APP_MEMORY_SIZE..]))
+    }
+}
+
+/// Index in `Process::stored_regs` of r9, the apps' GOT base register
+const GOT_REG: usize = 9 - 4;
+
+/// Most buffers other processes can share with a process at once, one for
+/// each MPU region left after the process's own two.
+pub const MAX_SHARED_BUFFERS: usize = 6;
+
+/// The MPU regions shared buffers are mapped through
+const SHARED_REGIONS: [RegionNum; MAX_SHARED_BUFFERS] = [
+    RegionNum::R2, RegionNum::R3, RegionNum::R4,
+    RegionNum::R5, RegionNum::R6, RegionNum::R7
+];
+
+/// Limits how far the restart backoff grows.
+const MAX_BACKOFF_DOUBLINGS: usize = 8;
+
+/// What happens to a process after it faults.
+#[derive(Copy,PartialEq,Eq)]
+pub enum RestartPolicy {
+    Never,
+    Always,
+    /// Restart at most this many times.
+    UpTo(usize)
+}
+
+impl RestartPolicy {
+    pub fn from_header(app: &AppHeader) -> RestartPolicy {
+        match app.restart_policy {
+            app_header::RESTART_ALWAYS => RestartPolicy::Always,
+            app_header::RESTART_UP_TO =>
+                RestartPolicy::UpTo(app.max_restarts as usize),
+            _ => RestartPolicy::Never
+        }
+    }
+}
 
 #[derive(Copy,PartialEq,Eq)]
 pub enum State {
     Running,
-    Waiting
+    Waiting,
+    /// The process caused a fault and its app's restart policy did not
+    /// restart it. It keeps its memory slot, so the kernel shell can still
+    /// show it and restart it, but is not scheduled until then.
+    Faulted,
+    /// The process called exit. Its memory slot has been released.
+    Exited,
+    /// The process was stopped from the kernel shell and is not scheduled
+    /// until it is resumed. Its callbacks stay queued.
+    Suspended
+}
+
+impl State {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            State::Running => "running",
+            State::Waiting => "waiting",
+            State::Faulted => "faulted",
+            State::Exited => "exited",
+            State::Suspended => "suspended"
+        }
+    }
 }
 
 
+/// A buffer another process shared with this one, which this process may
+/// read and write.
+#[derive(Copy)]
+pub struct SharedBuffer {
+    /// Memory slot of the process that owns the buffer
+    pub owner_slot: usize,
+    pub region: RegionLayout
+}
+
+impl SharedBuffer {
+    pub fn addr(&self) -> usize {
+        self.region.base
+    }
+
+    pub fn len(&self) -> usize {
+        1 << self.region.size_log2
+    }
+}
+
 #[derive(Copy)]
 pub struct Callback {
     pub r0: usize,
@@ -28,60 +142,257 @@ pub struct Callback {
 }
 
 pub struct Process<'a> {
+    /// The app's header in flash.
+    pub app: &'static AppHeader,
+
+    /// The app's name, from its header.
+    pub name: &'static str,
+
+    /// Index of the process's memory in `MEMORIES`.
+    pub slot: usize,
+
     /// The process's memory.
     pub memory: &'static mut [u8],
 
     /// The process's memory exposed to the process (the rest is reserved for the
-    /// kernel, drivers, etc).
+    /// kernel, drivers, etc). Starts with the app's GOT, data and bss, and
+    /// ends where the grant region begins.
     pub exposed_memory: &'static mut [u8],
 
     /// The offset in `memory` to use for the process stack.
     pub cur_stack: *mut u8,
 
+    /// Bytes at the top of `exposed_memory` set aside for the stack.
+    pub stack_size: usize,
+
+    /// The end of the process's heap, which starts right after its static
+    /// data. Moved by the memop system call.
+    pub app_break: *mut u8,
+
     pub wait_pc: usize,
 
+    /// The process's r4-r11, saved while the kernel runs.
+    pub stored_regs: [usize; 8],
+
     pub state: State,
+    /// The state a suspended process resumes in
+    pub resume_state: State,
+
+    pub priority: usize,
+
+    /// Address of the app's init function in flash, the first callback the
+    /// process runs.
+    pub init_fn: usize,
+
+    pub restart_policy: RestartPolicy,
 
-    pub callbacks: RingBuffer<'a, Callback>
+    /// Timer ticks to wait before the first restart.
+    pub backoff: u32,
+
+    /// The number of times the process was restarted.
+    pub restarts: usize,
+
+    /// What the process has used, for the kernel shell and the process
+    /// itself
+    pub stats: Stats,
+
+    pub callbacks: RingBuffer<'a, Callback>,
+
+    /// The MPU region the process can run and read its code through.
+    pub flash_region: RegionLayout,
+
+    /// The MPU region the process can read and write its memory through.
+    pub ram_region: RegionLayout,
+
+    /// Buffers other processes shared with this one, at most one from each.
+    pub shared_buffers: [Option<SharedBuffer>; MAX_SHARED_BUFFERS]
 }
 
 impl<'a> Process<'a> {
-    pub fn create(init_fn: fn()) -> Result<Process<'a>, ()> {
+    /// Creates a process for an app whose header has already been validated
+    /// and whose flash an MPU region covers exactly. Fails if there is no
+    /// free memory slot.
+    pub fn create(app: &'static AppHeader) -> Result<Process<'a>, ()> {
+        let cur_idx = match unsafe { SLOTS.alloc() } {
+            None => { return Err(()); },
+            Some(idx) => idx
+        };
+
         unsafe {
-            let cur_idx = atomic_xadd(&mut FREE_MEMORY_IDX, 1);
-            if cur_idx > MEMORIES.len() {
-                atomic_xadd(&mut FREE_MEMORY_IDX, -1);
-                Err(())
-            } else {
-                let memory = &mut MEMORIES[cur_idx];
-
-                let stack_bottom = &mut memory[PROC_MEMORY_SIZE - 4] as *mut u8;
-
-                // Take callback buffer from bottom of process memory
-                let callback_len = 10;
-                let callback_buf = mem::transmute(raw::Slice {
-                    data: &mut memory[0] as *mut u8 as *mut Option<Callback>,
-                    len: callback_len
-                });
-                let callback_size = mem::size_of::<Option<Callback>>();
+            let memory = &mut MEMORIES[cur_idx];
 
-                let mut callbacks = RingBuffer::new(callback_buf);
-                callbacks.enqueue(Callback {
-                    pc: init_fn as usize, r0: 0, r1: 0, r2:0
-                });
+            // Take callback buffer from bottom of process memory
+            let callback_len = app.callback_slots();
+            let callback_buf = mem::transmute(raw::Slice {
+                data: &mut memory[0] as *mut u8 as *mut Option<Callback>,
+                len: callback_len
+            });
+            // The ring takes up whole subregions, so the process's MPU region
+            // can leave it out: the kernel trusts what is in it.
+            let callback_size = mem::size_of::<Option<Callback>>();
+            let callbacks_end = app.callback_space(callback_size,
+                                                   SUBREGION_SIZE);
 
-                Ok(Process {
-                    memory: memory,
-                    exposed_memory: &mut memory[callback_len * callback_size..],
-                    cur_stack: stack_bottom as *mut u8,
-                    wait_pc: 0,
-                    state: State::Waiting,
-                    callbacks: callbacks
-                })
+            let flash_region = RegionLayout::covering_only(
+                app.start(), app.total_size as usize)
+                .expect("app flash is not aligned for the MPU");
+            // The callback ring below and the grant region above are whole
+            // subregions, so they are left out.
+            let ram_region = RegionLayout::covering_only(
+                memory.as_ptr() as usize + callbacks_end,
+                APP_MEMORY_SIZE - callbacks_end)
+                .expect("process memory is not aligned for the MPU");
+            if ram_region.size_log2 > PROC_MEMORY_SIZE_LOG2 {
+                panic!("Process memory is not aligned for the MPU");
             }
+
+            let mut process = Process {
+                app: app,
+                name: app.name().unwrap_or("?"),
+                slot: cur_idx,
+                memory: memory,
+                exposed_memory: &mut memory[callbacks_end..APP_MEMORY_SIZE],
+                cur_stack: 0 as *mut u8,
+                stack_size: app.stack_size as usize,
+                app_break: 0 as *mut u8,
+                wait_pc: 0,
+                stored_regs: [0; 8],
+                state: State::Waiting,
+                resume_state: State::Waiting,
+                priority: app.priority as usize,
+                init_fn: app.entry_point().unwrap_or(0),
+                restart_policy: RestartPolicy::from_header(app),
+                backoff: app.backoff,
+                restarts: 0,
+                stats: Stats::new(),
+                callbacks: RingBuffer::new(callback_buf),
+                flash_region: flash_region,
+                ram_region: ram_region,
+                shared_buffers: [None; MAX_SHARED_BUFFERS]
+            };
+            process.reset();
+            let init = process.init_callback();
+            process.callbacks.enqueue(init);
+            Ok(process)
+        }
+    }
+
+    /// Ends the process and gives its memory slot back for a new process to
+    /// use.
+    pub fn exit(&mut self) {
+        self.state = State::Exited;
+        while self.callbacks.dequeue().is_some() {}
+        unsafe {
+            SLOTS.free(self.slot);
         }
     }
 
+    /// Whether the process has stopped for good, either by faulting or by
+    /// exiting.
+    pub fn is_stopped(&self) -> bool {
+        self.state == State::Faulted || self.state == State::Exited
+    }
+
+    /// Puts the process back in the state it was created in, with fresh
+    /// static data, an empty stack, no pending callbacks, no grants and no
+    /// shared buffers.
+    pub fn reset(&mut self) {
+        unsafe { self.app.load(&mut *self.exposed_memory); }
+        self.grant_region().clear();
+        let stack_bottom = &mut self.memory[APP_MEMORY_SIZE - 4] as *mut u8;
+        self.cur_stack = stack_bottom;
+        self.app_break = self.heap_start();
+        self.wait_pc = 0;
+        self.stored_regs = [0; 8];
+        // Apps find their GOT, which the kernel just loaded at the bottom of
+        // their memory, through r9.
+        self.stored_regs[GOT_REG] = self.exposed_memory.as_ptr() as usize;
+        self.state = State::Waiting;
+        while self.callbacks.dequeue().is_some() {}
+        self.shared_buffers = [None; MAX_SHARED_BUFFERS];
+    }
+
+    /// The callback that starts the app from the beginning.
+    pub fn init_callback(&self) -> Callback {
+        Callback { pc: self.init_fn, r0: 0, r1: 0, r2: 0 }
+    }
+
+    /// Applies the app's restart policy to a process that has stopped. If
+    /// the process may run again it is reset and the number of timer ticks to
+    /// wait before running its init function is returned.
+    pub fn restart(&mut self) -> Option<u32> {
+        let allowed = match self.restart_policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::Always => true,
+            RestartPolicy::UpTo(max) => self.restarts < max
+        };
+        if !allowed {
+            // Nothing will use the drivers' state for the process unless the
+            // shell restarts it, which resets the process anyway. The
+            // process keeps its memory slot until it exits.
+            self.grant_region().clear();
+            return None;
+        }
+
+        let doublings = cmp::min(self.restarts, MAX_BACKOFF_DOUBLINGS);
+        // Stops at the longest delay rather than losing the bits shifted out.
+        let delay = if self.backoff > u32::MAX >> doublings {
+            u32::MAX
+        } else {
+            self.backoff << doublings
+        };
+        self.restarts += 1;
+        self.reset();
+        Some(delay)
+    }
+
+    /// Queues `callback` for the process and marks the process runnable.
+    /// Returns `false` if the process's callback queue is full, which is
+    /// counted in its stats, or the callback is not in the process's code.
+    pub fn enqueue_callback(&mut self, callback: Callback) -> bool {
+        if self.is_stopped() || !self.app.is_code_addr(callback.pc) {
+            return false;
+        }
+        if !self.callbacks.enqueue(callback) {
+            self.stats.callback_dropped();
+            return false;
+        }
+        self.wake();
+        true
+    }
+
+    /// Stops scheduling the process until `resume` is called. Returns
+    /// `false` if it has stopped for good or is already suspended.
+    pub fn suspend(&mut self) -> bool {
+        if self.is_stopped() || self.state == State::Suspended {
+            return false;
+        }
+        self.resume_state = self.state;
+        self.state = State::Suspended;
+        true
+    }
+
+    /// Schedules a suspended process again. Returns `false` if it was not
+    /// suspended.
+    pub fn resume(&mut self) -> bool {
+        if self.state != State::Suspended {
+            return false;
+        }
+        self.state = self.resume_state;
+        self.wake();
+        true
+    }
+
+    /// Puts the process on the ready queue so the kernel runs it. A
+    /// suspended process waits for `resume` instead.
+    pub fn wake(&mut self) {
+        if self.state == State::Suspended {
+            return;
+        }
+        let ptr = self as *mut Process as *mut ();
+        unsafe { scheduler::READY.wake(ptr, self.priority); }
+    }
+
     pub fn pop_syscall_stack(&mut self) {
         let pspr = self.cur_stack as *const usize;
         unsafe {
@@ -91,11 +402,69 @@ impl<'a> Process<'a> {
         }
     }
 
+    /// Where the process's heap starts, just above its static data.
+    pub fn heap_start(&self) -> *mut u8 {
+        let start = self.exposed_memory.as_ptr() as usize +
+            self.app.static_size();
+        start as *mut u8
+    }
+
+    /// The lowest address the process's stack may grow to, the top of its
+    /// heap.
+    pub fn stack_limit(&self) -> *mut u8 {
+        self.app_break
+    }
+
+    /// The highest the heap break may go: below both the current stack
+    /// pointer and the stack size the app asked for.
+    fn heap_limit(&self) -> *mut u8 {
+        let stack_top = self.memory.as_ptr() as usize + APP_MEMORY_SIZE;
+        let reserved = stack_top - self.stack_size;
+        cmp::min(reserved, self.cur_stack as usize) as *mut u8
+    }
+
+    /// Moves the heap break to `new_break`. Fails with `EINVAL` below the
+    /// start of the heap and with `ENOMEM` if the heap would run into the
+    /// stack.
+    pub fn brk(&mut self, new_break: *mut u8) -> Result<(), ReturnCode> {
+        let addr = new_break as usize;
+        if addr < self.heap_start() as usize {
+            Err(ReturnCode::EINVAL)
+        } else if addr > self.heap_limit() as usize {
+            Err(ReturnCode::ENOMEM)
+        } else {
+            self.app_break = new_break;
+            Ok(())
+        }
+    }
+
+    /// Grows (or with a negative `increment`, shrinks) the heap and returns
+    /// the old break.
+    pub fn sbrk(&mut self, increment: isize) -> Result<*mut u8, ReturnCode> {
+        let old_break = self.app_break;
+        let new_break = (old_break as isize).checked_add(increment)
+            .map(|addr| addr as *mut u8);
+        match new_break {
+            None => Err(ReturnCode::EINVAL),
+            Some(new_break) => self.brk(new_break).map(|()| old_break)
+        }
+    }
+
+    /// Bytes the stack can still grow by before it reaches the heap.
+    pub fn stack_left(&self) -> usize {
+        let (sp, limit) = (self.cur_stack as usize, self.app_break as usize);
+        if sp > limit { sp - limit } else { 0 }
+    }
+
     /// Context switch to the process.
     pub unsafe fn switch_to_callback(&mut self, callback: Callback) {
         // Fill in initial stack expected by SVC handler
         // Top minus 8 u32s for r0-r3, r12, lr, pc and xPSR
         let stack_bottom = (self.cur_stack as *mut usize).offset(-8);
+        if (stack_bottom as *mut u8) < self.stack_limit() {
+            self.state = State::Faulted;
+            return;
+        }
         volatile_store(stack_bottom.offset(7), 0x01000000);
         volatile_store(stack_bottom.offset(6), callback.pc);
         // Set the LR register to the saved PC so the callback returns to
@@ -107,16 +476,48 @@ impl<'a> Process<'a> {
         volatile_store(stack_bottom.offset(2), callback.r2);
 
         self.cur_stack = stack_bottom as *mut u8;
+        self.stats.callback_delivered();
         self.switch_to();
     }
 
-    /// Context switch to the process.
+    /// Limits the process to its own code and memory. Any other access it
+    /// makes raises a MemManage fault, which goes to the kernel.
+    unsafe fn configure_mpu(&self) {
+        let mut flash = Region::get(RegionNum::R0);
+        flash.set_address(self.flash_region.base);
+        flash.set_att_size(mpu::AP_READ_ONLY | mpu::FLASH_MEMORY |
+                           self.flash_region.att_size(), true);
+
+        let mut ram = Region::get(RegionNum::R1);
+        ram.set_address(self.ram_region.base);
+        ram.set_att_size(mpu::XN | mpu::AP_FULL_ACCESS | mpu::SRAM_MEMORY |
+                         self.ram_region.att_size(), true);
+
+        for i in range(0, MAX_SHARED_BUFFERS) {
+            let mut region = Region::get(SHARED_REGIONS[i]);
+            match self.shared_buffers[i] {
+                None => region.disable(),
+                Some(buf) => {
+                    region.set_address(buf.region.base);
+                    region.set_att_size(mpu::XN | mpu::AP_FULL_ACCESS |
+                                        mpu::SRAM_MEMORY |
+                                        buf.region.att_size(), true);
+                }
+            }
+        }
+    }
+
+    /// Context switch to the process. A process whose stack has overflowed
+    /// is marked `Faulted` instead.
     #[inline(never)]
     pub unsafe fn switch_to(&mut self) {
-        if self.cur_stack < (&mut self.exposed_memory[0] as *mut u8) {
-            asm!("bkpt" :::: "volatile");
+        if self.cur_stack < self.stack_limit() {
+            self.state = State::Faulted;
+            return;
         }
-        let psp = syscall::switch_to_user(self.cur_stack);
+        self.configure_mpu();
+        self.stats.context_switch();
+        let psp = syscall::switch_to_user(self.cur_stack, &mut self.stored_regs);
         self.cur_stack = psp;
     }
 
@@ -155,5 +556,84 @@ impl<'a> Process<'a> {
         unsafe { volatile_load(pspr.offset(2)) }
     }
 
+    pub fn r3(&self) -> usize {
+        let pspr = self.cur_stack as *const usize;
+        unsafe { volatile_load(pspr.offset(3)) }
+    }
+
+    /// Lets the process use `buf`, which belongs to the process in memory
+    /// slot `owner_slot`, in place of any buffer that process shared before.
+    /// The buffer must be an aligned block the MPU can cover exactly, so a
+    /// power of two of at least 32 bytes starting at a multiple of its size.
+    pub fn share_buffer(&mut self, owner_slot: usize, buf: &[u8])
+            -> Result<(), ReturnCode> {
+        let region = match RegionLayout::exactly(buf.as_ptr() as usize,
+                                                 buf.len()) {
+            None => { return Err(ReturnCode::EINVAL); },
+            Some(region) => region
+        };
+        self.unshare_buffer(owner_slot);
+        for entry in self.shared_buffers.iter_mut() {
+            if entry.is_none() {
+                *entry = Some(SharedBuffer {
+                    owner_slot: owner_slot,
+                    region: region
+                });
+                return Ok(());
+            }
+        }
+        Err(ReturnCode::ENOMEM)
+    }
+
+    /// The buffer the process in memory slot `owner_slot` shared with this
+    /// one, if any.
+    pub fn shared_buffer(&self, owner_slot: usize) -> Option<SharedBuffer> {
+        for entry in self.shared_buffers.iter() {
+            match *entry {
+                Some(buf) if buf.owner_slot == owner_slot => {
+                    return Some(buf);
+                },
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Stops sharing the buffer the process in memory slot `owner_slot`
+    /// shared with this one.
+    pub fn unshare_buffer(&mut self, owner_slot: usize) {
+        for entry in self.shared_buffers.iter_mut() {
+            let owned = match *entry {
+                Some(buf) => buf.owner_slot == owner_slot,
+                None => false
+            };
+            if owned {
+                *entry = None;
+            }
+        }
+    }
+
+    /// The part of the process's memory drivers keep their grants in.
+    pub fn grant_region(&mut self) -> GrantRegion {
+        GrantRegion::new(&mut self.memory[APP_MEMORY_SIZE..])
+    }
+
+    /// The `len` bytes at `ptr`, if they lie inside the process's exposed
+    /// memory.
+    pub fn exposed_slice(&self, ptr: usize, len: usize)
+            -> Option<&'static mut [u8]> {
+        let start = self.exposed_memory.as_ptr() as usize;
+        let end = start + self.exposed_memory.len();
+        if ptr < start || ptr > end || len > end - ptr {
+            return None;
+        }
+        unsafe {
+            Some(mem::transmute(raw::Slice {
+                data: ptr as *const u8,
+                len: len
+            }))
+        }
+    }
+
 }
 
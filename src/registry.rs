@@ -0,0 +1,80 @@
+use core::prelude::*;
+
+/// Most drivers that can be registered at once
+pub const MAX_DRIVERS: usize = 32;
+
+/// Maps the driver numbers processes pass in system calls to drivers.
+/// Numbers need not be contiguous.
+pub struct Registry<T: Copy> {
+    pub len: usize,
+    pub entries: [Option<(usize, T)>; MAX_DRIVERS]
+}
+
+impl<T: Copy> Registry<T> {
+    pub fn new() -> Registry<T> {
+        Registry { len: 0, entries: [None; MAX_DRIVERS] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Registers `driver` as driver number `num`. Returns `false` if `num` is
+    /// already taken or the registry is full.
+    pub fn register(&mut self, num: usize, driver: T) -> bool {
+        if self.len >= MAX_DRIVERS || self.get(num).is_some() {
+            return false;
+        }
+        self.entries[self.len] = Some((num, driver));
+        self.len += 1;
+        true
+    }
+
+    /// The driver registered as number `num`, if any.
+    pub fn get(&self, num: usize) -> Option<T> {
+        for i in range(0, self.len) {
+            match self.entries[i] {
+                Some((n, driver)) if n == num => { return Some(driver); },
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use super::{Registry, MAX_DRIVERS};
+
+    #[test]
+    fn finds_drivers_by_number() {
+        let mut drivers = Registry::new();
+        assert!(drivers.register(0, 'a'));
+        assert!(drivers.register(7, 'b'));
+        assert_eq!(drivers.get(0), Some('a'));
+        assert_eq!(drivers.get(7), Some('b'));
+        assert_eq!(drivers.get(1), None);
+        assert_eq!(drivers.len(), 2);
+    }
+
+    #[test]
+    fn refuses_duplicate_number() {
+        let mut drivers = Registry::new();
+        assert!(drivers.register(3, 'a'));
+        assert!(!drivers.register(3, 'b'));
+        assert_eq!(drivers.get(3), Some('a'));
+    }
+
+    #[test]
+    fn holds_more_than_ten_drivers() {
+        let mut drivers = Registry::new();
+        for i in range(0, MAX_DRIVERS) {
+            assert!(drivers.register(i * 2, i));
+        }
+        assert!(!drivers.register(1, 0));
+        for i in range(0, MAX_DRIVERS) {
+            assert_eq!(drivers.get(i * 2), Some(i));
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use core::prelude::*;
 
+use support;
+
 pub struct RingBuffer<'a, T: 'a> {
     pub head: usize,
     pub tail: usize,
@@ -17,13 +19,11 @@ impl <'a, T> RingBuffer<'a, T> {
         (self.tail + self.buf.len() - self.head) % self.buf.len()
     }
 
+    /// Adds `elm` at the tail. Returns `false` if the buffer is full. May be
+    /// used inside other critical sections.
     pub fn enqueue(&mut self, elm: T) -> bool {
-        unsafe {
-            asm!("mov r4, 1; msr PRIMASK, r4" :::"r4": "volatile");
-        }
-        let next_tail = (self.tail + 1) % self.buf.len();
-
-        let result =
+        support::atomic(|| {
+            let next_tail = (self.tail + 1) % self.buf.len();
             if next_tail == self.head {
                 // Do not continue if we may overrun the head of the element
                 // buffer.
@@ -32,29 +32,19 @@ impl <'a, T> RingBuffer<'a, T> {
                 self.buf[self.tail] = Some(elm);
                 self.tail = next_tail;
                 true
-            };
-        unsafe {
-            asm!("mov r4, 0; msr PRIMASK, r4" :::"r4": "volatile");
-        }
-        return result;
+            }
+        })
     }
 
+    /// Removes and returns the element at the head, if any.
     pub fn dequeue(&mut self) -> Option<T> {
-        unsafe {
-            asm!("mov r4, 1; msr PRIMASK, r4" :::"r4": "volatile");
-        }
-        let elm = self.buf[self.head].take();
-        let res = match elm {
-            None => None,
-            result@Some(_) => {
+        support::atomic(|| {
+            let elm = self.buf[self.head].take();
+            if elm.is_some() {
                 self.head = (self.head + 1) % self.buf.len();
-                result
             }
-        };
-        unsafe {
-            asm!("mov r4, 0; msr PRIMASK, r4" :::"r4": "volatile");
-        }
-        return res;
+            elm
+        })
     }
 
     pub fn peek(&self) -> &Option<T> {
@@ -62,3 +52,61 @@ impl <'a, T> RingBuffer<'a, T> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use super::RingBuffer;
+    use support;
+
+    #[test]
+    fn dequeues_in_order() {
+        let mut buf = [None; 4];
+        let mut ring = RingBuffer::new(&mut buf);
+        assert!(ring.enqueue(1));
+        assert!(ring.enqueue(2));
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.dequeue(), Some(1));
+        assert_eq!(ring.dequeue(), Some(2));
+        assert_eq!(ring.dequeue(), None);
+        assert_eq!(ring.len(), 0);
+    }
+
+    #[test]
+    fn refuses_when_full() {
+        // One entry is always left empty to tell a full buffer from an
+        // empty one.
+        let mut buf = [None; 4];
+        let mut ring = RingBuffer::new(&mut buf);
+        for i in range(0, 3) {
+            assert!(ring.enqueue(i));
+        }
+        assert!(!ring.enqueue(3));
+        assert_eq!(ring.len(), 3);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut buf = [None; 3];
+        let mut ring = RingBuffer::new(&mut buf);
+        for i in range(0, 10) {
+            assert!(ring.enqueue(i));
+            assert!(ring.enqueue(i + 100));
+            assert_eq!(ring.dequeue(), Some(i));
+            assert_eq!(ring.dequeue(), Some(i + 100));
+        }
+        assert_eq!(ring.dequeue(), None);
+    }
+
+    #[test]
+    fn keeps_interrupts_masked_in_critical_section() {
+        let mut buf = [None; 4];
+        let mut ring = RingBuffer::new(&mut buf);
+        support::atomic(|| {
+            ring.enqueue(1);
+            assert!(support::interrupts_masked());
+            ring.dequeue();
+            assert!(support::interrupts_masked());
+        });
+        assert!(!support::interrupts_masked());
+    }
+}
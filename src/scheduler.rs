@@ -0,0 +1,304 @@
+use core::prelude::*;
+
+/// Maximum number of processes that can be waiting to run at once.
+pub const MAX_PROCS: usize = 8;
+
+/// Picks which runnable process the kernel runs next.
+pub trait Scheduler<T> {
+    /// Marks `process` runnable. Waking a process that is already runnable
+    /// does nothing. Returns `false` if there is no room for the process.
+    fn wake(&mut self, process: T, priority: usize) -> bool;
+
+    /// Removes and returns the process to run next.
+    fn next(&mut self) -> Option<T>;
+
+    fn is_empty(&self) -> bool;
+}
+
+/// The scheduling policy, chosen at build time with
+/// `make SCHEDULER=<round_robin|priority>`.
+#[cfg(not(scheduler = "priority"))]
+pub type Policy = RoundRobin<*mut ()>;
+
+#[cfg(scheduler = "priority")]
+pub type Policy = FixedPriority<*mut ()>;
+
+/// Processes that have work to do.
+///
+/// Only used from the kernel's main loop. Interrupt handlers leave the work
+/// that wakes processes to deferred calls.
+#[cfg(not(scheduler = "priority"))]
+pub static mut READY: Policy = RoundRobin {
+    queue: RunQueue { head: 0, len: 0, items: [None; MAX_PROCS] }
+};
+
+#[cfg(scheduler = "priority")]
+pub static mut READY: Policy = FixedPriority {
+    len: 0, items: [None; MAX_PROCS]
+};
+
+/// Runs processes in the order they became runnable, ignoring priorities.
+pub struct RoundRobin<T: Copy + PartialEq> {
+    pub queue: RunQueue<T>
+}
+
+impl<T: Copy + PartialEq> RoundRobin<T> {
+    pub fn new() -> RoundRobin<T> {
+        RoundRobin { queue: RunQueue::new() }
+    }
+}
+
+impl<T: Copy + PartialEq> Scheduler<T> for RoundRobin<T> {
+    fn wake(&mut self, process: T, _: usize) -> bool {
+        self.queue.push(process)
+    }
+
+    fn next(&mut self) -> Option<T> {
+        self.queue.pop()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// Always runs the runnable process with the highest priority (the largest
+/// number). Processes with equal priority run in the order they became
+/// runnable.
+pub struct FixedPriority<T: Copy + PartialEq> {
+    pub len: usize,
+    /// Runnable processes and their priorities, oldest first.
+    pub items: [Option<(T, usize)>; MAX_PROCS]
+}
+
+impl<T: Copy + PartialEq> FixedPriority<T> {
+    pub fn new() -> FixedPriority<T> {
+        FixedPriority { len: 0, items: [None; MAX_PROCS] }
+    }
+}
+
+impl<T: Copy + PartialEq> Scheduler<T> for FixedPriority<T> {
+    fn wake(&mut self, process: T, priority: usize) -> bool {
+        for i in range(0, self.len) {
+            match self.items[i] {
+                Some((p, _)) if p == process => { return true; },
+                _ => {}
+            }
+        }
+        if self.len == MAX_PROCS {
+            return false;
+        }
+        self.items[self.len] = Some((process, priority));
+        self.len += 1;
+        true
+    }
+
+    fn next(&mut self) -> Option<T> {
+        let mut best: Option<(usize, usize)> = None;
+        for i in range(0, self.len) {
+            let (_, priority) = self.items[i].unwrap();
+            match best {
+                Some((_, best_priority)) if best_priority >= priority => {},
+                _ => { best = Some((i, priority)); }
+            }
+        }
+
+        best.map(|(idx, _)| {
+            let (process, _) = self.items[idx].unwrap();
+            for i in range(idx, self.len - 1) {
+                self.items[i] = self.items[i + 1];
+            }
+            self.len -= 1;
+            self.items[self.len] = None;
+            process
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// A fixed size FIFO of runnable processes.
+///
+/// A process is queued at most once. Pushing a process that is already
+/// waiting to run leaves its place in the queue unchanged.
+pub struct RunQueue<T: Copy + PartialEq> {
+    pub head: usize,
+    pub len: usize,
+    pub items: [Option<T>; MAX_PROCS]
+}
+
+impl<T: Copy + PartialEq> RunQueue<T> {
+    pub fn new() -> RunQueue<T> {
+        RunQueue { head: 0, len: 0, items: [None; MAX_PROCS] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, item: T) -> bool {
+        for i in range(0, self.len) {
+            if self.items[(self.head + i) % MAX_PROCS] == Some(item) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Appends `item` to the back of the queue unless it is already queued.
+    /// Returns `false` only if the queue is full.
+    pub fn push(&mut self, item: T) -> bool {
+        if self.contains(item) {
+            return true;
+        }
+        if self.len == MAX_PROCS {
+            return false;
+        }
+        let tail = (self.head + self.len) % MAX_PROCS;
+        self.items[tail] = Some(item);
+        self.len += 1;
+        true
+    }
+
+    /// Removes and returns the item at the front of the queue.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = self.items[self.head].take();
+        self.head = (self.head + 1) % MAX_PROCS;
+        self.len -= 1;
+        item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use super::{RunQueue, RoundRobin, FixedPriority, Scheduler, MAX_PROCS};
+
+    #[test]
+    fn pops_in_fifo_order() {
+        let mut queue = RunQueue::new();
+        assert!(queue.push(1));
+        assert!(queue.push(2));
+        assert!(queue.push(3));
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn queues_a_process_once() {
+        let mut queue = RunQueue::new();
+        assert!(queue.push(7));
+        assert!(queue.push(8));
+        assert!(queue.push(7));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some(7));
+        assert_eq!(queue.pop(), Some(8));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn requeue_after_pop() {
+        let mut queue = RunQueue::new();
+        queue.push(1);
+        assert_eq!(queue.pop(), Some(1));
+        assert!(!queue.contains(1));
+        assert!(queue.push(1));
+        assert_eq!(queue.pop(), Some(1));
+    }
+
+    #[test]
+    fn refuses_push_when_full() {
+        let mut queue = RunQueue::new();
+        for i in range(0, MAX_PROCS) {
+            assert!(queue.push(i));
+        }
+        assert!(!queue.push(MAX_PROCS));
+        // Already queued items are still accepted
+        assert!(queue.push(0));
+        assert_eq!(queue.len(), MAX_PROCS);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut queue = RunQueue::new();
+        for round in range(0, 3) {
+            for i in range(0, MAX_PROCS) {
+                assert!(queue.push(round * MAX_PROCS + i));
+            }
+            for i in range(0, MAX_PROCS) {
+                assert_eq!(queue.pop(), Some(round * MAX_PROCS + i));
+            }
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn round_robin_ignores_priority() {
+        let mut sched = RoundRobin::new();
+        assert!(sched.wake(1, 0));
+        assert!(sched.wake(2, 5));
+        assert!(sched.wake(1, 9));
+        assert_eq!(sched.next(), Some(1));
+        assert_eq!(sched.next(), Some(2));
+        assert_eq!(sched.next(), None);
+    }
+
+    #[test]
+    fn priority_runs_most_urgent_first() {
+        let mut sched = FixedPriority::new();
+        assert!(sched.wake(1, 0));
+        assert!(sched.wake(2, 3));
+        assert!(sched.wake(3, 1));
+        assert_eq!(sched.next(), Some(2));
+        assert_eq!(sched.next(), Some(3));
+        assert_eq!(sched.next(), Some(1));
+        assert_eq!(sched.next(), None);
+        assert!(sched.is_empty());
+    }
+
+    #[test]
+    fn priority_is_fifo_among_equals() {
+        let mut sched = FixedPriority::new();
+        sched.wake(1, 2);
+        sched.wake(2, 2);
+        sched.wake(3, 2);
+        assert_eq!(sched.next(), Some(1));
+        // A process that runs again goes behind its peers
+        sched.wake(1, 2);
+        assert_eq!(sched.next(), Some(2));
+        assert_eq!(sched.next(), Some(3));
+        assert_eq!(sched.next(), Some(1));
+    }
+
+    #[test]
+    fn priority_queues_a_process_once() {
+        let mut sched = FixedPriority::new();
+        sched.wake(1, 0);
+        sched.wake(1, 0);
+        assert_eq!(sched.next(), Some(1));
+        assert_eq!(sched.next(), None);
+    }
+
+    #[test]
+    fn priority_refuses_wake_when_full() {
+        let mut sched = FixedPriority::new();
+        for i in range(0, MAX_PROCS) {
+            assert!(sched.wake(i, i));
+        }
+        assert!(!sched.wake(MAX_PROCS, 0));
+        assert_eq!(sched.next(), Some(MAX_PROCS - 1));
+        assert!(sched.wake(MAX_PROCS, 0));
+    }
+}
@@ -0,0 +1,248 @@
+//! The kernel shell: commands typed on the console to look at and manage
+//! processes on the device, without a debugger. This module reads the
+//! commands; the kernel's main loop runs them.
+
+use core::prelude::*;
+use core::str;
+
+/// Longest command line the shell keeps. Bytes typed past it are dropped.
+pub const MAX_LINE: usize = 32;
+
+/// Ctrl-T switches console input between the shell and the process reading
+/// the console.
+pub const TOGGLE_BYTE: u8 = 0x14;
+
+pub const PROMPT: &'static str = "tock> ";
+
+/// Lines `help` prints
+pub static HELP: [&'static str; 10] = [
+    "ps              list processes",
+    "stats <slot>    show what the process in memory slot <slot> has used",
+    "stop <slot>     suspend the process in memory slot <slot>",
+    "start <slot>    resume a suspended process",
+    "restart <slot>  run a process again from the beginning",
+    "load <app>      start a process for the app named <app>",
+    "drivers         list the drivers processes can use",
+    "chip            show what the chip identifies itself as",
+    "uptime          show how long the kernel has been running",
+    "help            show this list"
+];
+
+#[derive(Copy, PartialEq, Eq, Debug)]
+pub enum Command<'a> {
+    Help,
+    /// Lists every process with its state, memory slot and callback queue
+    /// depth.
+    Processes,
+    /// Prints a process's counters.
+    Stats(usize),
+    Stop(usize),
+    Start(usize),
+    Restart(usize),
+    /// Starts a process for the app with this name, taking a memory slot a
+    /// process that exited gave up if need be.
+    Load(&'a str),
+    Drivers,
+    ChipInfo,
+    Uptime
+}
+
+#[derive(Copy, PartialEq, Eq, Debug)]
+pub enum ParseError {
+    /// The line is blank, which is not an error to report.
+    Empty,
+    Unknown,
+    /// The command needs a slot number and did not get one.
+    BadSlot,
+    /// The command needs an app name and did not get one.
+    BadName
+}
+
+impl ParseError {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            ParseError::Empty => "No command",
+            ParseError::Unknown => "Unknown command, try help",
+            ParseError::BadSlot => "Expected a slot number",
+            ParseError::BadName => "Expected an app name"
+        }
+    }
+}
+
+/// Parses a decimal number, rejecting anything else.
+fn parse_num(word: &[u8]) -> Option<usize> {
+    if word.len() == 0 {
+        return None;
+    }
+    let mut num: usize = 0;
+    for &byte in word.iter() {
+        if byte < b'0' || byte > b'9' {
+            return None;
+        }
+        num = match num.checked_mul(10).and_then(|n| {
+            n.checked_add((byte - b'0') as usize)
+        }) {
+            None => { return None; },
+            Some(n) => n
+        };
+    }
+    Some(num)
+}
+
+impl<'a> Command<'a> {
+    /// Parses a command line: a command name and, for commands that act on a
+    /// process, its slot number or app name, separated by spaces.
+    pub fn parse(line: &'a [u8]) -> Result<Command<'a>, ParseError> {
+        let mut words = line.split(|&byte| byte == b' ')
+                            .filter(|word| word.len() > 0);
+        let name = match words.next() {
+            None => { return Err(ParseError::Empty); },
+            Some(name) => name
+        };
+        let arg = words.next();
+        if words.next().is_some() {
+            return Err(ParseError::Unknown);
+        }
+        let name = match str::from_utf8(name) {
+            Err(_) => { return Err(ParseError::Unknown); },
+            Ok(name) => name
+        };
+
+        match name {
+            "stats" | "stop" | "start" | "restart" => {
+                let slot = match arg.and_then(parse_num) {
+                    None => { return Err(ParseError::BadSlot); },
+                    Some(slot) => slot
+                };
+                Ok(match name {
+                    "stats" => Command::Stats(slot),
+                    "stop" => Command::Stop(slot),
+                    "start" => Command::Start(slot),
+                    _ => Command::Restart(slot)
+                })
+            },
+            "load" => match arg.and_then(|arg| str::from_utf8(arg).ok()) {
+                None => Err(ParseError::BadName),
+                Some(app) => Ok(Command::Load(app))
+            },
+            _ if arg.is_some() => Err(ParseError::Unknown),
+            "help" => Ok(Command::Help),
+            "ps" => Ok(Command::Processes),
+            "drivers" => Ok(Command::Drivers),
+            "chip" => Ok(Command::ChipInfo),
+            "uptime" => Ok(Command::Uptime),
+            _ => Err(ParseError::Unknown)
+        }
+    }
+}
+
+/// What the console should do with a byte given to a `LineBuffer`
+#[derive(Copy, PartialEq, Eq, Debug)]
+pub enum Input {
+    /// Echo the byte, which was added to the line.
+    Echo,
+    /// Erase the last byte on the terminal.
+    Erase,
+    Ignore,
+    /// The line is complete.
+    Done
+}
+
+/// The command line being typed
+pub struct LineBuffer {
+    pub len: usize,
+    pub buf: [u8; MAX_LINE]
+}
+
+impl LineBuffer {
+    pub fn new() -> LineBuffer {
+        LineBuffer { len: 0, buf: [0; MAX_LINE] }
+    }
+
+    /// Adds a byte typed on the console to the line. Backspace and delete
+    /// take the last byte back, and other control bytes are ignored.
+    pub fn input(&mut self, byte: u8) -> Input {
+        match byte {
+            b'\r' | b'\n' => Input::Done,
+            0x08 | 0x7f if self.len > 0 => {
+                self.len -= 1;
+                Input::Erase
+            },
+            0x20...0x7e if self.len < MAX_LINE => {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                Input::Echo
+            },
+            _ => Input::Ignore
+        }
+    }
+
+    pub fn line(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use super::{Command, Input, LineBuffer, ParseError, MAX_LINE};
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse(b"ps"), Ok(Command::Processes));
+        assert_eq!(Command::parse(b"stats 1"), Ok(Command::Stats(1)));
+        assert_eq!(Command::parse(b"  stop 3 "), Ok(Command::Stop(3)));
+        assert_eq!(Command::parse(b"start 12"), Ok(Command::Start(12)));
+        assert_eq!(Command::parse(b"restart 0"), Ok(Command::Restart(0)));
+        assert_eq!(Command::parse(b"load blink"), Ok(Command::Load("blink")));
+        assert_eq!(Command::parse(b"drivers"), Ok(Command::Drivers));
+        assert_eq!(Command::parse(b"chip"), Ok(Command::ChipInfo));
+        assert_eq!(Command::parse(b"uptime"), Ok(Command::Uptime));
+        assert_eq!(Command::parse(b"help"), Ok(Command::Help));
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert_eq!(Command::parse(b""), Err(ParseError::Empty));
+        assert_eq!(Command::parse(b"   "), Err(ParseError::Empty));
+        assert_eq!(Command::parse(b"reboot"), Err(ParseError::Unknown));
+        assert_eq!(Command::parse(b"ps 1"), Err(ParseError::Unknown));
+        assert_eq!(Command::parse(b"stop 1 2"), Err(ParseError::Unknown));
+        assert_eq!(Command::parse(b"stop"), Err(ParseError::BadSlot));
+        assert_eq!(Command::parse(b"stop x1"), Err(ParseError::BadSlot));
+        assert_eq!(Command::parse(b"load"), Err(ParseError::BadName));
+        assert_eq!(Command::parse(b"load \xff"), Err(ParseError::BadName));
+        assert_eq!(Command::parse(b"load a b"), Err(ParseError::Unknown));
+        assert_eq!(Command::parse(b"stop 99999999999999999999999"),
+                   Err(ParseError::BadSlot));
+    }
+
+    #[test]
+    fn edits_line() {
+        let mut line = LineBuffer::new();
+        assert_eq!(line.input(b'p'), Input::Echo);
+        assert_eq!(line.input(b'x'), Input::Echo);
+        assert_eq!(line.input(0x7f), Input::Erase);
+        assert_eq!(line.input(0x1b), Input::Ignore);
+        assert_eq!(line.input(b's'), Input::Echo);
+        assert_eq!(line.input(b'\r'), Input::Done);
+        assert_eq!(line.line(), &b"ps"[..]);
+        line.clear();
+        assert_eq!(line.input(0x08), Input::Ignore);
+        assert_eq!(line.line(), &b""[..]);
+    }
+
+    #[test]
+    fn drops_bytes_past_max_line() {
+        let mut line = LineBuffer::new();
+        for _ in range(0, MAX_LINE) {
+            assert_eq!(line.input(b'a'), Input::Echo);
+        }
+        assert_eq!(line.input(b'a'), Input::Ignore);
+        assert_eq!(line.line().len(), MAX_LINE);
+    }
+}
@@ -0,0 +1,104 @@
+use core::prelude::*;
+
+/// Number of process memory slots
+pub const NUM_SLOTS: usize = 8;
+
+/// Hands out process memory slots and takes them back when a process ends.
+///
+/// Only used from the kernel's main loop, never from interrupt handlers.
+pub struct SlotAllocator {
+    pub used: [bool; NUM_SLOTS]
+}
+
+impl SlotAllocator {
+    pub fn new() -> SlotAllocator {
+        SlotAllocator { used: [false; NUM_SLOTS] }
+    }
+
+    /// Returns the lowest free slot and marks it used, or `None` if every
+    /// slot is taken.
+    pub fn alloc(&mut self) -> Option<usize> {
+        for slot in range(0, NUM_SLOTS) {
+            if !self.used[slot] {
+                self.used[slot] = true;
+                return Some(slot);
+            }
+        }
+        None
+    }
+
+    /// Returns `slot` to the allocator. Returns `false` if the slot does not
+    /// exist or was not allocated.
+    pub fn free(&mut self, slot: usize) -> bool {
+        if slot >= NUM_SLOTS || !self.used[slot] {
+            return false;
+        }
+        self.used[slot] = false;
+        true
+    }
+
+    pub fn is_used(&self, slot: usize) -> bool {
+        slot < NUM_SLOTS && self.used[slot]
+    }
+
+    pub fn num_free(&self) -> usize {
+        self.used.iter().filter(|&&used| !used).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use super::{SlotAllocator, NUM_SLOTS};
+
+    #[test]
+    fn allocates_every_slot_once() {
+        let mut slots = SlotAllocator::new();
+        for i in range(0, NUM_SLOTS) {
+            assert_eq!(slots.alloc(), Some(i));
+        }
+        assert_eq!(slots.alloc(), None);
+        assert_eq!(slots.num_free(), 0);
+    }
+
+    #[test]
+    fn reuses_freed_slot() {
+        let mut slots = SlotAllocator::new();
+        for _ in range(0, NUM_SLOTS) {
+            slots.alloc();
+        }
+        assert!(slots.free(3));
+        assert!(!slots.is_used(3));
+        assert_eq!(slots.alloc(), Some(3));
+        assert_eq!(slots.alloc(), None);
+    }
+
+    #[test]
+    fn refuses_double_free() {
+        let mut slots = SlotAllocator::new();
+        let slot = slots.alloc().unwrap();
+        assert!(slots.free(slot));
+        assert!(!slots.free(slot));
+        assert_eq!(slots.num_free(), NUM_SLOTS);
+    }
+
+    #[test]
+    fn refuses_out_of_range_slot() {
+        let mut slots = SlotAllocator::new();
+        assert!(!slots.free(NUM_SLOTS));
+        assert!(!slots.is_used(NUM_SLOTS));
+    }
+
+    #[test]
+    fn hands_out_lowest_free_slot() {
+        let mut slots = SlotAllocator::new();
+        slots.alloc();
+        slots.alloc();
+        slots.alloc();
+        slots.free(0);
+        slots.free(2);
+        assert_eq!(slots.alloc(), Some(0));
+        assert_eq!(slots.alloc(), Some(2));
+        assert_eq!(slots.alloc(), Some(3));
+    }
+}
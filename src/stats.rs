@@ -0,0 +1,125 @@
+use core::prelude::*;
+
+/// System calls there are, numbered as in `syscall`
+pub const NUM_SYSCALLS: usize = 6;
+
+/// Numbers of the counters processes read with `MEMOP_STATS`. The first
+/// `NUM_SYSCALLS` count system calls by number. Must match `tock.h`.
+pub const STAT_CALLBACKS_DELIVERED: usize = NUM_SYSCALLS;
+pub const STAT_CALLBACKS_DROPPED: usize = NUM_SYSCALLS + 1;
+pub const STAT_CONTEXT_SWITCHES: usize = NUM_SYSCALLS + 2;
+pub const STAT_TIMESLICES: usize = NUM_SYSCALLS + 3;
+pub const NUM_STATS: usize = NUM_SYSCALLS + 4;
+
+/// Names of the counters, by number
+pub static STAT_NAMES: [&'static str; NUM_STATS] = [
+    "wait", "subscribe", "command", "allow", "exit", "memop",
+    "callbacks delivered", "callbacks dropped", "context switches",
+    "timeslices used"
+];
+
+/// What a process has done and had done to it since it was created. Kept
+/// across restarts. Counters stop at `u32::MAX` rather than wrapping.
+#[derive(Copy)]
+pub struct Stats {
+    /// System calls made, by number
+    pub syscalls: [u32; NUM_SYSCALLS],
+    pub callbacks_delivered: u32,
+    /// Callbacks lost because the process's callback queue was full
+    pub callbacks_dropped: u32,
+    /// Times the kernel switched to the process
+    pub context_switches: u32,
+    /// Timeslices the process used up and was preempted at the end of
+    pub timeslices: u32
+}
+
+fn bump(count: &mut u32) {
+    *count = count.saturating_add(1);
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            syscalls: [0; NUM_SYSCALLS],
+            callbacks_delivered: 0,
+            callbacks_dropped: 0,
+            context_switches: 0,
+            timeslices: 0
+        }
+    }
+
+    /// Counts a call to system call number `num`. Calls to numbers that are
+    /// not system calls are not counted.
+    pub fn syscall(&mut self, num: usize) {
+        if num < NUM_SYSCALLS {
+            bump(&mut self.syscalls[num]);
+        }
+    }
+
+    pub fn callback_delivered(&mut self) {
+        bump(&mut self.callbacks_delivered);
+    }
+
+    pub fn callback_dropped(&mut self) {
+        bump(&mut self.callbacks_dropped);
+    }
+
+    pub fn context_switch(&mut self) {
+        bump(&mut self.context_switches);
+    }
+
+    pub fn timeslice(&mut self) {
+        bump(&mut self.timeslices);
+    }
+
+    /// Counter number `stat`, or `None` if there is no such counter.
+    pub fn get(&self, stat: usize) -> Option<u32> {
+        match stat {
+            STAT_CALLBACKS_DELIVERED => Some(self.callbacks_delivered),
+            STAT_CALLBACKS_DROPPED => Some(self.callbacks_dropped),
+            STAT_CONTEXT_SWITCHES => Some(self.context_switches),
+            STAT_TIMESLICES => Some(self.timeslices),
+            _ if stat < NUM_SYSCALLS => Some(self.syscalls[stat]),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use core::u32;
+    use super::{Stats, NUM_STATS, NUM_SYSCALLS, STAT_CALLBACKS_DROPPED,
+                STAT_TIMESLICES};
+
+    #[test]
+    fn counts_syscalls_by_number() {
+        let mut stats = Stats::new();
+        stats.syscall(2);
+        stats.syscall(2);
+        stats.syscall(0);
+        stats.syscall(NUM_SYSCALLS);
+        assert_eq!(stats.get(0), Some(1));
+        assert_eq!(stats.get(1), Some(0));
+        assert_eq!(stats.get(2), Some(2));
+        assert_eq!(stats.get(NUM_STATS), None);
+    }
+
+    #[test]
+    fn reads_each_counter() {
+        let mut stats = Stats::new();
+        stats.callback_dropped();
+        stats.timeslice();
+        stats.timeslice();
+        assert_eq!(stats.get(STAT_CALLBACKS_DROPPED), Some(1));
+        assert_eq!(stats.get(STAT_TIMESLICES), Some(2));
+    }
+
+    #[test]
+    fn counters_stop_at_max() {
+        let mut stats = Stats::new();
+        stats.context_switches = u32::MAX;
+        stats.context_switch();
+        assert_eq!(stats.context_switches, u32::MAX);
+    }
+}
@@ -1,3 +1,4 @@
+use core::prelude::*;
 use core::fmt::Arguments;
 use core::intrinsics::*;
 
@@ -37,11 +38,22 @@ pub extern fn eh_personality() {}
 #[lang="begin_unwind"]
 pub extern fn begin_unwind() {}
 
+/// Called with the panic message and location before the system halts, so
+/// the kernel can report it once it is able to print.
+pub static mut PANIC_HOOK: Option<fn(&Arguments, &(&'static str, usize))> = None;
+
 #[cfg(not(test))]
 #[lang="panic_fmt"]
 #[no_mangle]
-pub extern fn rust_begin_unwind(_fmt: &Arguments,
-    _file_line: &(&'static str, usize)) -> ! {
+pub extern fn rust_begin_unwind(fmt: &Arguments,
+    file_line: &(&'static str, usize)) -> ! {
+  unsafe {
+    // Cleared first so a panic inside the hook does not recurse.
+    if let Some(hook) = PANIC_HOOK {
+      PANIC_HOOK = None;
+      hook(fmt, file_line);
+    }
+  }
   loop { }
 }
 
@@ -0,0 +1,70 @@
+//! Critical sections: code that must not be interrupted.
+//!
+//! On the host, PRIMASK is simulated so code that uses critical sections can
+//! be tested there.
+
+#[cfg(not(test))]
+/// Runs `f` with interrupts masked, then puts PRIMASK back the way it was.
+/// Critical sections nest: only the outermost one unmasks interrupts when it
+/// ends, and one entered with interrupts already masked leaves them masked.
+pub fn atomic<R, F: FnOnce() -> R>(f: F) -> R {
+    let primask: usize;
+    unsafe {
+        asm!("mrs $0, PRIMASK; cpsid i" : "=r"(primask) :: "memory" : "volatile");
+    }
+    let res = f();
+    if primask & 1 == 0 {
+        unsafe { asm!("cpsie i" ::: "memory" : "volatile"); }
+    }
+    res
+}
+
+#[cfg(not(test))]
+/// Whether interrupts are masked (PRIMASK is set)
+pub fn interrupts_masked() -> bool {
+    let primask: usize;
+    unsafe { asm!("mrs $0, PRIMASK" : "=r"(primask) ::: "volatile"); }
+    primask & 1 == 1
+}
+
+// Simulated PRIMASK, one for each test thread
+#[cfg(test)]
+thread_local!(static MASKED: ::std::cell::Cell<bool> =
+              ::std::cell::Cell::new(false));
+
+#[cfg(test)]
+/// Runs `f` with interrupts masked (mock)
+pub fn atomic<R, F: FnOnce() -> R>(f: F) -> R {
+    let masked = interrupts_masked();
+    MASKED.with(|m| m.set(true));
+    let res = f();
+    MASKED.with(|m| m.set(masked));
+    res
+}
+
+#[cfg(test)]
+/// Whether interrupts are masked (mock)
+pub fn interrupts_masked() -> bool {
+    MASKED.with(|masked| masked.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{atomic, interrupts_masked};
+
+    #[test]
+    fn masks_interrupts_inside() {
+        assert!(!interrupts_masked());
+        assert_eq!(atomic(|| interrupts_masked()), true);
+        assert!(!interrupts_masked());
+    }
+
+    #[test]
+    fn nested_section_leaves_interrupts_masked() {
+        atomic(|| {
+            atomic(|| assert!(interrupts_masked()));
+            assert!(interrupts_masked());
+        });
+        assert!(!interrupts_masked());
+    }
+}
@@ -28,5 +28,7 @@
 extern crate core;
 
 pub use arm::*;
+pub use critical_section::*;
 
 mod arm;
+mod critical_section;
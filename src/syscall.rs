@@ -1,70 +1,156 @@
+use core::prelude::*;
+
+use driver::Driver;
+use process::Process;
+use registry::{Registry, MAX_DRIVERS};
+
 #[allow(improper_ctypes)]
 extern {
-    pub fn switch_to_user(user_stack: *mut u8) -> *mut u8;
+    pub fn switch_to_user(user_stack: *mut u8, stored_regs: &mut [usize; 8])
+        -> *mut u8;
 }
 
-pub type SyscallFunc = fn(*mut (), usize, usize) -> isize;
+/// Why the last process to run handed the CPU back to the kernel. Written by
+/// the exception handlers in `ctx_switch.S`.
+#[no_mangle]
+pub static mut SWITCH_REASON: usize = SWITCH_SYSCALL;
 
-fn noop(_: *mut (), _: usize, _: usize) -> isize { -1 }
+/// The process made a system call.
+pub const SWITCH_SYSCALL: usize = 0;
+/// The process used up its timeslice.
+pub const SWITCH_TIMESLICE: usize = 1;
+/// The process caused a HardFault, MemManage, BusFault or UsageFault.
+pub const SWITCH_FAULT: usize = 2;
 
-pub static mut SUBSCRIBE_DRIVERS: [SyscallFunc; 10] = [noop; 10];
-pub static mut NUM_SUBSCRIBE_DRIVERS: usize = 0;
+/// Status of a system call, passed back to the process in r0. Drivers that
+/// return a value on success use nonnegative numbers for it, so every
+/// `ReturnCode` other than `SUCCESS` is negative.
+///
+/// Must match `enum return_code` in `apps/c/lib/inc/tock.h`.
+#[derive(Copy,PartialEq,Eq)]
+pub enum ReturnCode {
+    SUCCESS = 0,
+    /// Generic failure
+    FAIL = -1,
+    /// The driver is in use and cannot take the request now.
+    EBUSY = -2,
+    /// An argument is out of range.
+    EINVAL = -3,
+    /// The driver ran out of room, e.g. for more timers.
+    ENOMEM = -4,
+    /// A buffer is too small or too large.
+    ESIZE = -5,
+    /// There is no driver with the requested number.
+    ENODEVICE = -6,
+    /// There is no system call with the requested number.
+    ENOSUPPORT = -7,
+    /// The app did not declare that it uses the driver.
+    EPERM = -8,
+}
 
-pub static mut CMD_DRIVERS: [SyscallFunc; 10] = [noop; 10];
-pub static mut NUM_CMD_DRIVERS: usize = 0;
+impl ReturnCode {
+    /// The value placed in the process's r0.
+    pub fn to_isize(self) -> isize {
+        self as isize
+    }
+}
 
-pub const WAIT: u8 = 0;
-pub const SUBSCRIBE: u8 = 1;
-pub const COMMAND: u8 = 2;
+/// The drivers processes can use, by driver number. Filled in by
+/// `config::config`.
+pub static mut DRIVERS: Registry<*mut Driver> = Registry {
+    len: 0, entries: [None; MAX_DRIVERS]
+};
 
-#[derive(Copy)]
-pub enum ReturnTo {
-  Process = 0,
-  Kernel = 1
+/// Calls `subscribe` on driver `driver_num` for `process`. The function to
+/// call back, `callback`, must lie in the process's code, or the kernel
+/// could be made to run the process from anywhere.
+pub unsafe fn subscribe(process: &mut Process, driver_num: usize,
+                        r1: usize, callback: usize) -> isize {
+    let driver = match DRIVERS.get(driver_num) {
+        None => { return ReturnCode::ENODEVICE.to_isize(); },
+        Some(driver) => driver
+    };
+    if !process.app.is_code_addr(callback) {
+        return ReturnCode::EINVAL.to_isize();
+    }
+    (*driver).subscribe(process, r1, callback)
 }
 
-/*#[no_mangle]
-#[allow(unused_assignments)]
-/// Called from the SVC handler.
-pub unsafe extern fn svc_rust_handler(psp: usize) -> u16 {
-    use core::intrinsics::volatile_load;
+/// Calls `command` on driver `driver_num` for `process`.
+pub unsafe fn command(process: &mut Process, driver_num: usize,
+                      r1: usize, r2: usize) -> isize {
+    match DRIVERS.get(driver_num) {
+        None => ReturnCode::ENODEVICE.to_isize(),
+        Some(driver) => (*driver).command(process, r1, r2)
+    }
+}
 
-    /* Find process PC on stack */
-    let user_pc = volatile_load((psp + 24) as *const usize);
-    let r0 = volatile_load(psp as *const usize);
-    let r1 = volatile_load((psp + 4) as *const usize);
-    let r2 = volatile_load((psp + 8) as *const usize);
+/// Lends `len` bytes of `process`'s memory at `ptr` to driver `driver_num`.
+/// The buffer must lie inside the process's exposed memory.
+pub unsafe fn allow(process: &mut Process, driver_num: usize,
+                    allow_num: usize, ptr: usize, len: usize) -> isize {
+    let driver = match DRIVERS.get(driver_num) {
+        None => { return ReturnCode::ENODEVICE.to_isize(); },
+        Some(driver) => driver
+    };
+    match process.exposed_slice(ptr, len) {
+        None => ReturnCode::EINVAL.to_isize(),
+        Some(buf) => (*driver).allow(process, allow_num, buf)
+    }
+}
 
-    /* SVC is one instruction before current PC. The low byte is the opcode */
-    let svc = volatile_load((user_pc - 2) as *const u16) & 0xff;
+/// Operations of the memop system call, passed in r0
+pub const MEMOP_BRK: usize = 0;
+pub const MEMOP_SBRK: usize = 1;
+pub const MEMOP_MEMORY_START: usize = 2;
+pub const MEMOP_MEMORY_END: usize = 3;
+pub const MEMOP_HEAP_START: usize = 4;
+pub const MEMOP_STACK_LEFT: usize = 5;
+pub const MEMOP_STATS: usize = 6;
 
-    return svc;
-    /*match svc {
-        WAIT => {
-            volatile_store(psp as *mut isize, 0);
+/// Manages `process`'s heap and tells it about its memory.
+///
+/// * `MEMOP_BRK` moves the heap break to `arg`.
+/// * `MEMOP_SBRK` moves the break by `arg`, taken as signed, and returns the
+///   old break.
+/// * `MEMOP_MEMORY_START` and `MEMOP_MEMORY_END` return the bounds of the
+///   memory the process can use.
+/// * `MEMOP_HEAP_START` returns where the heap starts, after static data.
+/// * `MEMOP_STACK_LEFT` returns how many bytes the stack can still grow by.
+/// * `MEMOP_STATS` returns the process's counter number `arg`, one of the
+///   `stats::STAT_` numbers.
+pub fn memop(process: &mut Process, op: usize, arg: usize) -> isize {
+    let start = process.exposed_memory.as_ptr() as usize;
+    match op {
+        MEMOP_BRK => match process.brk(arg as *mut u8) {
+            Ok(()) => ReturnCode::SUCCESS.to_isize(),
+            Err(err) => err.to_isize()
         },
-        SUBSCRIBE => {
-            if r0 > NUM_SUBSCRIBE_DRIVERS {
-                volatile_store(psp as *mut isize, -1);
-            }
-
-            let res = SUBSCRIBE_DRIVERS[r0](r1, r2);
-            volatile_store(psp as *mut isize, res);
-            return ReturnTo::Process;
+        MEMOP_SBRK => match process.sbrk(arg as isize) {
+            Ok(old_break) => old_break as isize,
+            Err(err) => err.to_isize()
         },
-        COMMAND => {
-            if r0 > NUM_CMD_DRIVERS {
-                volatile_store(psp as *mut isize, -1);
-            }
-
-            let res = CMD_DRIVERS[r0](r1, r2);
-            volatile_store(psp as *mut isize, res);
-            return ReturnTo::Process;
+        MEMOP_MEMORY_START => start as isize,
+        MEMOP_MEMORY_END => (start + process.exposed_memory.len()) as isize,
+        MEMOP_HEAP_START => process.heap_start() as isize,
+        MEMOP_STACK_LEFT => process.stack_left() as isize,
+        MEMOP_STATS => match process.stats.get(arg) {
+            Some(count) => count as isize,
+            None => ReturnCode::EINVAL.to_isize()
         },
-        _ => {
-            volatile_store(psp as *mut isize, -1);
-        }
+        _ => ReturnCode::EINVAL.to_isize()
     }
-    return ReturnTo::Kernel;*/
 }
-*/
+
+pub const WAIT: u8 = 0;
+pub const SUBSCRIBE: u8 = 1;
+pub const COMMAND: u8 = 2;
+pub const ALLOW: u8 = 3;
+pub const EXIT: u8 = 4;
+pub const MEMOP: u8 = 5;
+
+#[derive(Copy)]
+pub enum ReturnTo {
+  Process = 0,
+  Kernel = 1
+}
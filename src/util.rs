@@ -1,6 +1,15 @@
 use core::prelude::*;
+use core::fmt;
 use config;
 
+pub fn print(val: &str) {
+    let mut console = unsafe {
+        config::Console.as_mut().expect("Console is None!")
+    };
+
+    console.write(val);
+}
+
 pub fn println(val: &str) {
     let mut console = unsafe {
         config::Console.as_mut().expect("Console is None!")
@@ -9,9 +18,39 @@ pub fn println(val: &str) {
     console.writeln(val);
 }
 
+/// Prints `val` as eight hex digits prefixed with "0x", without a newline.
+pub fn print_hex(val: usize) {
+    let mut console = unsafe {
+        config::Console.as_mut().expect("Console is None!")
+    };
+
+    console.write("0x");
+    for i in range(0, 8) {
+        let digit = (val >> (28 - 4 * i)) & 0xf;
+        let c = if digit < 10 { digit + 0x30 } else { digit - 10 + 0x61 };
+        console.putc(c as u8);
+    }
+}
+
+/// Lets formatted messages, such as panic messages, go to the console.
+pub struct ConsoleWriter;
+
+impl fmt::Write for ConsoleWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print(s);
+        Ok(())
+    }
+}
+
+pub fn print_num(val: u32) {
+    print_dec(val);
+    print("\n");
+}
+
+/// Prints `val` in decimal, without a newline.
 // gratefully borrowed from
 //  http://www.sparetimelabs.com/tinyprintf/tinyprintf.php
-pub fn print_num(val: u32) {
+pub fn print_dec(val: u32) {
     let mut console = unsafe {
         config::Console.as_mut().expect("Console is None!")
     };
@@ -35,7 +74,5 @@ pub fn print_num(val: u32) {
             first = false;
         }
     }
-
-    console.putc('\n' as u8);
 }
 